@@ -0,0 +1,60 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use async_rust_webserver::server::methods::HttpMethod;
+use async_rust_webserver::server::pattern::PathPattern;
+use async_rust_webserver::server::tree::RouteTree;
+
+// Builds a table shaped like a versioned REST API: each resource gets a
+// collection route, an item route with a parameter and a nested static
+// route, plus a handful of wildcard mounts for static assets.
+fn build_tree(resources: usize) -> RouteTree<usize> {
+    let mut tree = RouteTree::new();
+    let mut id = 0;
+
+    for resource in 0..resources {
+        for pattern in [
+            format!("/api/v1/resource{}", resource),
+            format!("/api/v1/resource{}/{{id}}", resource),
+            format!("/api/v1/resource{}/{{id}}/history", resource),
+        ] {
+            let pattern = PathPattern::parse(&pattern).unwrap();
+            tree.insert(HttpMethod::GET, &pattern, id).unwrap();
+            id += 1;
+        }
+    }
+
+    for directory in ["css", "js", "images", "fonts"] {
+        let pattern = PathPattern::parse(&format!("/{}/*path", directory)).unwrap();
+        tree.insert(HttpMethod::GET, &pattern, id).unwrap();
+        id += 1;
+    }
+
+    tree
+}
+
+fn route_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("route_lookup");
+
+    for resources in [10, 100, 500] {
+        let tree = build_tree(resources);
+        let last = resources - 1;
+
+        let paths = [
+            ("static", format!("/api/v1/resource{}", last)),
+            ("param", format!("/api/v1/resource{}/42/history", last)),
+            ("wildcard", "/fonts/inter/regular.woff2".to_string()),
+            ("miss", format!("/api/v2/resource{}/42", last)),
+        ];
+
+        for (kind, path) in &paths {
+            group.bench_with_input(BenchmarkId::new(*kind, resources * 3), path, |b, path| {
+                b.iter(|| tree.find(black_box(&HttpMethod::GET), black_box(path)).map(|found| *found.value))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, route_lookup);
+criterion_main!(benches);
@@ -0,0 +1,6 @@
+pub mod server;
+pub mod routes;
+pub mod middleware;
+pub mod models;
+pub mod services;
+pub mod utils;
@@ -1,20 +1,21 @@
-mod server;
-mod routes;
-mod models;
-mod services;
-mod utils;
-
 use std::env;
 use dotenv::dotenv;
-use tracing::{info, error};
+use tracing::info;
 use std::sync::Arc;
 use std::error::Error;
 use tokio::signal;
 use tokio::net::TcpListener;
 
-use crate::server::server::HttpServer;
-use crate::services::data_sync::DataSyncService;
-use crate::services::database::Database;
+use async_rust_webserver::middleware::access_log::AccessLog;
+use async_rust_webserver::middleware::https_redirect::HttpsRedirect;
+use async_rust_webserver::routes::build_router;
+use async_rust_webserver::server::config::ServerConfig;
+use async_rust_webserver::server::http2::alpn_protocols;
+use async_rust_webserver::server::router::Router;
+use async_rust_webserver::server::server::HttpServer;
+use async_rust_webserver::server::tls::{spawn_certificate_reloader, tls_acceptor, CertResolver, TlsConfig};
+use async_rust_webserver::services::data_sync::DataSyncService;
+use async_rust_webserver::services::database::Database;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -43,27 +44,42 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     info!("Spinning up server...");
 
-    let http_server = HttpServer::new(Arc::clone(&database_arc));
+    let router = build_router(Arc::clone(&database_arc))?;
+    let server_config = ServerConfig::from_env();
+    let http2_enabled = server_config.http2.enabled;
+    let http_server = HttpServer::new(router, server_config.clone());
     let http_server = Arc::new(http_server);
     let listener = TcpListener::bind(format!("{}:{}", ip_address, port)).await?;
 
+    let acceptor = match TlsConfig::from_env()? {
+        Some(tls_config) => {
+            if let Some(redirect_port) = tls_config.redirect_port {
+                let https_port = port.parse::<u16>().expect("PORT must be a valid port");
+                let redirect_listener = TcpListener::bind(format!("{}:{}", ip_address, redirect_port)).await?;
+                let mut redirect_router = Router::new();
+                redirect_router.layer(Arc::new(AccessLog));
+                redirect_router.layer(Arc::new(HttpsRedirect::new(https_port)));
 
-    loop {
-        tokio::select! {
-            Ok((stream, _)) = listener.accept() => {
-                let server = Arc::clone(&http_server);
-
-                tokio::spawn(async move {
-                    if let Err(e) = server.handle_connection(stream).await {
-                        error!("Error handling connection: {}", e);
-                    }
-                });
+                info!("Redirecting HTTP on port {} to HTTPS", redirect_port);
+                let redirect_server = Arc::new(HttpServer::new(redirect_router, server_config));
+                tokio::spawn(redirect_server.serve(redirect_listener, None));
             }
 
-            _ = signal::ctrl_c() => {
-                info!("Shutting down server...");
-                break;
-            }
+            let reload_interval = tls_config.reload_interval;
+            let resolver = Arc::new(CertResolver::new(tls_config)?);
+            spawn_certificate_reloader(Arc::clone(&resolver), reload_interval);
+
+            info!("Serving HTTPS on port {}", port);
+            Some(tls_acceptor(resolver, alpn_protocols(http2_enabled))?)
+        }
+        None => None,
+    };
+
+    tokio::select! {
+        _ = http_server.serve(listener, acceptor) => {}
+
+        _ = signal::ctrl_c() => {
+            info!("Shutting down server...");
         }
     }
 
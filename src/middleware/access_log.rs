@@ -0,0 +1,28 @@
+use std::time::Instant;
+use tracing::info;
+
+use crate::server::middleware::{Middleware, Next};
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::utils::error::ApplicationError;
+
+// One line per request with the outcome and how long the handler took
+pub struct AccessLog;
+
+#[async_trait::async_trait]
+impl Middleware for AccessLog {
+    async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, ApplicationError> {
+        let method = req.method().as_str().to_string();
+        let path = req.path().clone();
+        let started = Instant::now();
+
+        let result = next.run(req).await;
+
+        match &result {
+            Ok(response) => info!("{} {} -> {} in {:?}", method, path, response.status_code(), started.elapsed()),
+            Err(e) => info!("{} {} -> error in {:?}: {}", method, path, started.elapsed(), e),
+        }
+
+        result
+    }
+}
@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use crate::models::api_key::{ApiKey, ApiScope};
+use crate::server::extract::FromRequest;
+use crate::server::middleware::{Middleware, Next};
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::services::api_keys::ApiKeys;
+use crate::utils::error::ApplicationError;
+
+const REALM: &str = "api";
+
+// The key a request was authenticated with, put in the request extensions
+// by `ApiKeyAuth`
+#[derive(Debug, Clone)]
+pub struct AuthenticatedKey(pub ApiKey);
+
+impl FromRequest for AuthenticatedKey {
+    fn from_request(req: &Request) -> Result<Self, ApplicationError> {
+        req.extension::<AuthenticatedKey>().cloned().ok_or_else(|| ApplicationError::RequestRejected {
+            status: 401,
+            message: "API key required".to_string(),
+        })
+    }
+}
+
+// Requires an API key with `scope`, sent as `Authorization: Bearer <key>` or
+// in an `X-API-Key` header. Failures are answered the way RFC 6750 describes
+// for bearer tokens: 401 for a missing or unusable key, 403 for a valid key
+// without the scope, each with a WWW-Authenticate challenge.
+pub struct ApiKeyAuth {
+    keys: Arc<ApiKeys>,
+    scope: ApiScope,
+}
+
+impl ApiKeyAuth {
+    pub fn new(keys: Arc<ApiKeys>, scope: ApiScope) -> Self {
+        Self { keys, scope }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for ApiKeyAuth {
+    async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, ApplicationError> {
+        let Some(presented) = presented_key(&req) else {
+            // No error code when no credentials were sent at all
+            return Ok(challenge(401, "API key required", &format!("Bearer realm=\"{}\"", REALM)));
+        };
+
+        let Some(api_key) = self.keys.authenticate(presented).await? else {
+            let description = "The API key is invalid, expired or revoked";
+            return Ok(challenge(
+                401,
+                description,
+                &format!("Bearer realm=\"{}\", error=\"invalid_token\", error_description=\"{}\"", REALM, description),
+            ));
+        };
+
+        if !api_key.allows(self.scope) {
+            let description = format!("The API key lacks the '{}' scope", self.scope);
+            return Ok(challenge(
+                403,
+                &description,
+                &format!(
+                    "Bearer realm=\"{}\", error=\"insufficient_scope\", error_description=\"{}\", scope=\"{}\"",
+                    REALM, description, self.scope
+                ),
+            ));
+        }
+
+        next.run(req.with_extension(AuthenticatedKey(api_key))).await
+    }
+}
+
+// Other Authorization schemes, like Basic added by a proxy, are not ours to
+// judge, so X-API-Key is still looked at when one is present
+fn presented_key(req: &Request) -> Option<&str> {
+    let bearer = req
+        .headers()
+        .get("authorization")
+        .and_then(|authorization| authorization.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, credentials)| credentials.trim());
+
+    bearer.or_else(|| req.headers().get("x-api-key").map(str::trim))
+}
+
+fn challenge(status: u16, message: &str, www_authenticate: &str) -> Response {
+    let rejection = ApplicationError::RequestRejected { status, message: message.to_string() };
+    Response::from_error(&rejection).with_header("WWW-Authenticate", www_authenticate)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use super::*;
+    use crate::server::route::Route;
+    use crate::server::router::Router;
+    use crate::server::methods::HttpMethod;
+    use crate::services::database::Database;
+
+    struct KeyName;
+
+    #[async_trait::async_trait]
+    impl Route for KeyName {
+        async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+            let AuthenticatedKey(api_key) = AuthenticatedKey::from_request(&req)?;
+            Ok(Response::new(200, "OK").with_text_body(&api_key.name))
+        }
+    }
+
+    async fn setup() -> (Router, Arc<ApiKeys>) {
+        let database = Arc::new(Database::new("sqlite::memory:".to_string()).await.unwrap());
+        let keys = Arc::new(ApiKeys::new(database, None));
+
+        let mut router = Router::new();
+        let mut quotes = router.group().layer(Arc::new(ApiKeyAuth::new(Arc::clone(&keys), ApiScope::ReadQuotes)));
+        quotes.add_route(HttpMethod::GET, "/quotes", Arc::new(KeyName)).unwrap();
+
+        (router, keys)
+    }
+
+    async fn get(router: &Router, headers: &str) -> Response {
+        let raw = format!("GET /quotes HTTP/1.1\r\n{}\r\n", headers);
+        router.route(Request::try_from(raw.as_bytes()).unwrap()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn accepts_keys_in_either_header() {
+        let (router, keys) = setup().await;
+        let (key, _) = keys.mint("reader", &[ApiScope::ReadQuotes], None).await.unwrap();
+
+        for headers in [
+            format!("Authorization: Bearer {}\r\n", key),
+            format!("authorization: bearer  {} \r\n", key),
+            format!("X-API-Key: {}\r\n", key),
+            format!("Authorization: Basic dXNlcjpwYXNz\r\nX-API-Key: {}\r\n", key),
+        ] {
+            let response = get(&router, &headers).await;
+            assert_eq!(response.status_code(), 200, "for {:?}", headers);
+            assert_eq!(response.content_length(), Some("reader".len() as u64));
+        }
+    }
+
+    #[tokio::test]
+    async fn challenges_requests_without_a_key() {
+        let (router, _) = setup().await;
+
+        for headers in ["", "Authorization: Basic dXNlcjpwYXNz\r\n"] {
+            let response = get(&router, headers).await;
+            assert_eq!(response.status_code(), 401);
+            assert_eq!(response.header("WWW-Authenticate"), Some("Bearer realm=\"api\""));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_expired_and_revoked_keys() {
+        let (router, keys) = setup().await;
+        let yesterday = Utc::now() - Duration::days(1);
+        let (expired, _) = keys.mint("expired", &[ApiScope::ReadQuotes], Some(yesterday)).await.unwrap();
+        let (revoked, revoked_key) = keys.mint("revoked", &[ApiScope::ReadQuotes], None).await.unwrap();
+        assert!(keys.revoke(revoked_key.id).await.unwrap());
+
+        for key in ["ak_made_up", expired.as_str(), revoked.as_str()] {
+            let response = get(&router, &format!("Authorization: Bearer {}\r\n", key)).await;
+            assert_eq!(response.status_code(), 401, "for {}", key);
+            let challenge = response.header("WWW-Authenticate").unwrap();
+            assert!(challenge.starts_with("Bearer realm=\"api\", error=\"invalid_token\""), "{}", challenge);
+        }
+    }
+
+    #[tokio::test]
+    async fn forbids_keys_without_the_scope() {
+        let (router, keys) = setup().await;
+        let (writer, _) = keys.mint("writer", &[ApiScope::ManageWatchlists], None).await.unwrap();
+        let (admin, _) = keys.mint("admin", &[ApiScope::Admin], None).await.unwrap();
+
+        let response = get(&router, &format!("X-API-Key: {}\r\n", writer)).await;
+        assert_eq!(response.status_code(), 403);
+        let challenge = response.header("WWW-Authenticate").unwrap();
+        assert!(challenge.contains("error=\"insufficient_scope\""), "{}", challenge);
+        assert!(challenge.ends_with("scope=\"quotes:read\""), "{}", challenge);
+
+        // Admin implies every other scope
+        assert_eq!(get(&router, &format!("X-API-Key: {}\r\n", admin)).await.status_code(), 200);
+    }
+}
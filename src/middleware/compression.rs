@@ -0,0 +1,128 @@
+use std::env;
+
+use crate::server::compression::{negotiate, ContentEncoding};
+use crate::server::config::env_or;
+use crate::server::middleware::{Middleware, Next};
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::utils::error::ApplicationError;
+
+// Below this, the encoding overhead eats most of what compression saves
+const DEFAULT_MIN_SIZE: u64 = 1024;
+// Formats that are already compressed, like images, gain nothing
+const DEFAULT_CONTENT_TYPES: &str = "text/html, text/css, text/plain, text/csv, application/json, \
+                                     application/javascript, image/svg+xml";
+
+// Compresses response bodies with whichever of brotli, gzip or deflate the
+// client prefers (see `negotiate`). Only text-like content types of some
+// size are compressed; a body the handler already encoded, such as a
+// precompressed static file, is passed through untouched.
+pub struct Compression {
+    min_size: u64,
+    content_types: Vec<String>,
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Self { min_size: DEFAULT_MIN_SIZE, content_types: parse_content_types(DEFAULT_CONTENT_TYPES) }
+    }
+
+    // COMPRESSION_MIN_SIZE in bytes, and COMPRESSION_CONTENT_TYPES as a
+    // comma-separated list of media types
+    pub fn from_env() -> Self {
+        let mut compression = Self::new().with_min_size(env_or("COMPRESSION_MIN_SIZE", DEFAULT_MIN_SIZE));
+
+        if let Ok(content_types) = env::var("COMPRESSION_CONTENT_TYPES") {
+            compression = compression.with_content_types(&content_types);
+        }
+
+        compression
+    }
+
+    pub fn with_min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    pub fn with_content_types(mut self, content_types: &str) -> Self {
+        self.content_types = parse_content_types(content_types);
+        self
+    }
+
+    fn compressible(&self, response: &Response) -> bool {
+        let status = response.status_code();
+        let bodiless = status < 200 || status == 204 || status == 304;
+
+        // Parameters such as "; charset=utf-8" do not matter here
+        let content_type = response
+            .header("Content-Type")
+            .and_then(|value| value.split(';').next())
+            .map(str::trim)
+            .unwrap_or_default();
+
+        let no_transform = response
+            .headers()
+            .get_all("Cache-Control")
+            .any(|value| value.split(',').any(|directive| directive.trim().eq_ignore_ascii_case("no-transform")));
+
+        !bodiless
+            && !no_transform
+            && response.header("Content-Range").is_none()
+            && self.content_types.iter().any(|allowed| allowed.eq_ignore_ascii_case(content_type))
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for Compression {
+    async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, ApplicationError> {
+        let accept_encoding = req.headers().get_combined("accept-encoding");
+        let mut response = next.run(req).await?;
+
+        if !self.compressible(&response) {
+            return Ok(response);
+        }
+
+        // Another client may be sent a different encoding of the same URL,
+        // so caches have to key on the header whether or not this one is
+        if !varies_on_accept_encoding(&response) {
+            response = response.with_appended_header("Vary", "Accept-Encoding");
+        }
+
+        if response.header("Content-Encoding").is_some() {
+            return Ok(response);
+        }
+
+        // A stream of unknown length is assumed to be worth it
+        if response.content_length().is_some_and(|length| length < self.min_size) {
+            return Ok(response);
+        }
+
+        match negotiate(accept_encoding.as_deref(), &ContentEncoding::ALL) {
+            Some(encoding) => Ok(response.with_content_encoding(encoding)?),
+            None => Ok(response),
+        }
+    }
+}
+
+fn varies_on_accept_encoding(response: &Response) -> bool {
+    response
+        .headers()
+        .get_all("Vary")
+        .flat_map(|value| value.split(','))
+        .any(|name| name.trim().eq_ignore_ascii_case("Accept-Encoding") || name.trim() == "*")
+}
+
+fn parse_content_types(content_types: &str) -> Vec<String> {
+    content_types
+        .split(',')
+        .map(str::trim)
+        .filter(|content_type| !content_type.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect()
+}
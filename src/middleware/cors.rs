@@ -0,0 +1,273 @@
+use std::env;
+
+use crate::server::config::env_or;
+use crate::server::headers::HeaderMap;
+use crate::server::methods::HttpMethod;
+use crate::server::middleware::{Middleware, Next};
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::utils::error::ApplicationError;
+
+const DEFAULT_METHODS: &str = "GET, HEAD, POST, DELETE";
+const DEFAULT_HEADERS: &str = "Authorization, Content-Type, X-API-Key";
+const DEFAULT_EXPOSED_HEADERS: &str = "Location, WWW-Authenticate, Retry-After, RateLimit-Limit, RateLimit-Remaining, RateLimit-Reset";
+const DEFAULT_MAX_AGE: u64 = 600;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowedOrigin {
+    Any,
+    Exact(String),
+    // "https://*.example.com": anything in place of the '*' except another
+    // scheme, port or path
+    Pattern { prefix: String, suffix: String },
+}
+
+impl AllowedOrigin {
+    pub fn parse(origin: &str) -> Self {
+        let origin = origin.trim().to_ascii_lowercase();
+
+        match origin.split_once('*') {
+            _ if origin == "*" => AllowedOrigin::Any,
+            Some((prefix, suffix)) => AllowedOrigin::Pattern { prefix: prefix.to_string(), suffix: suffix.to_string() },
+            None => AllowedOrigin::Exact(origin),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            AllowedOrigin::Any => true,
+            AllowedOrigin::Exact(allowed) => allowed.eq_ignore_ascii_case(origin),
+            AllowedOrigin::Pattern { prefix, suffix } => {
+                let origin = origin.to_ascii_lowercase();
+                let Some(host) = origin
+                    .strip_prefix(prefix.as_str())
+                    .and_then(|rest| rest.strip_suffix(suffix.as_str()))
+                else {
+                    return false;
+                };
+
+                !host.is_empty()
+                    && host.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'.')
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<AllowedOrigin>,
+    pub allowed_methods: Vec<HttpMethod>,
+    // Compared case-insensitively; "*" allows whatever a preflight asks for
+    pub allowed_headers: Vec<String>,
+    // Response headers beyond the CORS-safelisted ones that scripts may read
+    pub exposed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    // How long a browser may cache a preflight answer, in seconds
+    pub max_age: u64,
+}
+
+impl CorsConfig {
+    // `None` when CORS_ALLOWED_ORIGINS is unset, which leaves CORS off and
+    // cross-origin scripts locked out as before. The other settings are
+    // CORS_ALLOWED_METHODS, CORS_ALLOWED_HEADERS, CORS_EXPOSED_HEADERS,
+    // CORS_ALLOW_CREDENTIALS and CORS_MAX_AGE.
+    pub fn from_env() -> Result<Option<Self>, ApplicationError> {
+        let Ok(origins) = env::var("CORS_ALLOWED_ORIGINS") else {
+            return Ok(None);
+        };
+
+        let allowed_methods = split_list(&env_or("CORS_ALLOWED_METHODS", DEFAULT_METHODS.to_string()))
+            .into_iter()
+            .map(|method| HttpMethod::try_from(method.as_str()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| ApplicationError::InvalidEnvVar(format!("CORS_ALLOWED_METHODS: {}", e)))?;
+
+        let config = Self {
+            allowed_origins: split_list(&origins).iter().map(|origin| AllowedOrigin::parse(origin)).collect(),
+            allowed_methods,
+            allowed_headers: split_list(&env_or("CORS_ALLOWED_HEADERS", DEFAULT_HEADERS.to_string())),
+            exposed_headers: split_list(&env_or("CORS_EXPOSED_HEADERS", DEFAULT_EXPOSED_HEADERS.to_string())),
+            allow_credentials: env_or("CORS_ALLOW_CREDENTIALS", false),
+            max_age: env_or("CORS_MAX_AGE", DEFAULT_MAX_AGE),
+        };
+
+        // Browsers refuse credentials with a wildcard origin, and echoing
+        // every origin instead would hand any site the user's cookies
+        if config.allow_credentials && config.allowed_origins.contains(&AllowedOrigin::Any) {
+            return Err(ApplicationError::InvalidEnvVar(
+                "CORS_ALLOW_CREDENTIALS cannot be combined with a '*' origin".to_string(),
+            ));
+        }
+
+        Ok(Some(config))
+    }
+}
+
+// Lets pages on the configured origins call the server from the browser.
+// Preflights (OPTIONS with Access-Control-Request-Method) are answered here
+// without reaching the routes; other cross-origin responses get the
+// Access-Control-* headers added. Requests without an Origin header, and
+// those outside `path_prefix`, pass through untouched.
+pub struct Cors {
+    config: CorsConfig,
+    path_prefix: String,
+}
+
+impl Cors {
+    pub fn new(config: CorsConfig) -> Self {
+        Self { config, path_prefix: "/".to_string() }
+    }
+
+    pub fn with_path_prefix(mut self, path_prefix: &str) -> Self {
+        self.path_prefix = path_prefix.to_string();
+        self
+    }
+
+    fn origin_allowed(&self, origin: &str) -> bool {
+        self.config.allowed_origins.iter().any(|allowed| allowed.matches(origin))
+    }
+
+    fn header_allowed(&self, name: &str) -> bool {
+        self.config
+            .allowed_headers
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(name))
+    }
+
+    fn is_wildcard(&self) -> bool {
+        self.config.allowed_origins.contains(&AllowedOrigin::Any)
+    }
+
+    fn preflight(&self, origin: &str, headers: &HeaderMap) -> Response {
+        let method = headers.get("access-control-request-method").unwrap_or_default();
+        let requested_headers = headers.get_combined("access-control-request-headers").unwrap_or_default();
+        let requested_headers = split_list(&requested_headers);
+
+        let method_allowed = self.config.allowed_methods.iter().any(|allowed| allowed.as_str() == method);
+        if !self.origin_allowed(origin) || !method_allowed || !requested_headers.iter().all(|name| self.header_allowed(name)) {
+            let rejection = ApplicationError::RequestRejected {
+                status: 403,
+                message: "Cross-origin request not allowed".to_string(),
+            };
+            return Response::from_error(&rejection).with_appended_header("Vary", "Origin");
+        }
+
+        let methods: Vec<&str> = self.config.allowed_methods.iter().map(HttpMethod::as_str).collect();
+        let allowed_headers = if self.config.allowed_headers.iter().any(|allowed| allowed == "*") {
+            requested_headers.join(", ")
+        } else {
+            self.config.allowed_headers.join(", ")
+        };
+
+        let response = Response::new(204, "No Content")
+            .with_header("Access-Control-Allow-Methods", &methods.join(", "))
+            .with_header("Access-Control-Max-Age", &self.config.max_age.to_string())
+            .with_appended_header("Vary", "Access-Control-Request-Method, Access-Control-Request-Headers");
+        let response = if allowed_headers.is_empty() {
+            response
+        } else {
+            response.with_header("Access-Control-Allow-Headers", &allowed_headers)
+        };
+
+        self.decorate(response, origin)
+    }
+
+    fn decorate(&self, response: Response, origin: &str) -> Response {
+        // The answer depends on the Origin unless every origin gets "*"
+        let response = if self.is_wildcard() {
+            response.with_header("Access-Control-Allow-Origin", "*")
+        } else {
+            response
+                .with_header("Access-Control-Allow-Origin", origin)
+                .with_appended_header("Vary", "Origin")
+        };
+
+        let response = if self.config.allow_credentials {
+            response.with_header("Access-Control-Allow-Credentials", "true")
+        } else {
+            response
+        };
+
+        if self.config.exposed_headers.is_empty() {
+            response
+        } else {
+            response.with_header("Access-Control-Expose-Headers", &self.config.exposed_headers.join(", "))
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for Cors {
+    async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, ApplicationError> {
+        let origin = match req.headers().get("origin") {
+            Some(origin) if req.path().starts_with(&self.path_prefix) => origin.to_string(),
+            _ => return next.run(req).await,
+        };
+
+        if *req.method() == HttpMethod::OPTIONS && req.headers().contains("access-control-request-method") {
+            return Ok(self.preflight(&origin, req.headers()));
+        }
+
+        let response = next.run(req).await?;
+
+        if self.origin_allowed(&origin) {
+            Ok(self.decorate(response, &origin))
+        } else {
+            Ok(response.with_appended_header("Vary", "Origin"))
+        }
+    }
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_origin_kinds() {
+        assert_eq!(AllowedOrigin::parse(" * "), AllowedOrigin::Any);
+        assert_eq!(AllowedOrigin::parse("HTTPS://App.Example.com"), AllowedOrigin::Exact("https://app.example.com".to_string()));
+        assert_eq!(
+            AllowedOrigin::parse("https://*.example.com"),
+            AllowedOrigin::Pattern { prefix: "https://".to_string(), suffix: ".example.com".to_string() }
+        );
+    }
+
+    #[test]
+    fn exact_origin_matches_case_insensitively() {
+        let allowed = AllowedOrigin::parse("https://app.example.com");
+
+        assert!(allowed.matches("https://app.example.com"));
+        assert!(allowed.matches("https://APP.example.com"));
+        assert!(!allowed.matches("http://app.example.com"));
+        assert!(!allowed.matches("https://app.example.com:8443"));
+        assert!(!allowed.matches("null"));
+    }
+
+    #[test]
+    fn pattern_only_matches_subdomains() {
+        let allowed = AllowedOrigin::parse("https://*.example.com");
+
+        assert!(allowed.matches("https://app.example.com"));
+        assert!(allowed.matches("https://a.b.example.com"));
+
+        for origin in [
+            "https://example.com",
+            "http://app.example.com",
+            "https://app.example.com:8443",
+            "https://evil.com/.example.com",
+            "https://user@app.example.com",
+            "https://app.example.com.evil.com",
+            "https://app_example.com",
+        ] {
+            assert!(!allowed.matches(origin), "matched {}", origin);
+        }
+    }
+}
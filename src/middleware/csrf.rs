@@ -0,0 +1,393 @@
+use std::sync::{Arc, Mutex};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use rand::RngCore;
+use rand::rngs::OsRng;
+
+use crate::middleware::session::Session;
+use crate::server::cookie::{Cookie, CookieKey, SameSite};
+use crate::server::extract::FromRequest;
+use crate::server::methods::HttpMethod;
+use crate::server::middleware::{Middleware, Next};
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::server::urlencoded::parse_urlencoded;
+use crate::utils::error::ApplicationError;
+
+const CSRF_TOKEN_BYTES: usize = 32;
+const SESSION_KEY: &str = "csrf_token";
+const COOKIE_NAME: &str = "csrf";
+
+pub const CSRF_FIELD: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+// The visitor's CSRF token, for pages with forms. Put it in the template and
+// write `{{ csrf_token.field()|safe }}` inside each `<form method="post">`.
+#[derive(Clone)]
+pub struct CsrfToken(Vec<u8>);
+
+impl CsrfToken {
+    // Masked afresh on every call, see `mask`
+    pub fn value(&self) -> String {
+        mask(&self.0)
+    }
+
+    // A hidden input carrying the token. The token is URL-safe base64, so it
+    // needs no escaping.
+    pub fn field(&self) -> String {
+        format!(r#"<input type="hidden" name="{}" value="{}">"#, CSRF_FIELD, self.value())
+    }
+}
+
+// Reuses the visitor's token, or starts one. A visitor who already has a
+// session keeps the token in it; anyone else gets it in a signed cookie, so
+// showing a form to an anonymous visitor never stores a session. Needs the
+// session and CSRF middleware.
+impl FromRequest for CsrfToken {
+    fn from_request(req: &Request) -> Result<Self, ApplicationError> {
+        let session = Session::from_request(req)?;
+
+        if let Some(token) = session.get::<String>(SESSION_KEY).as_deref().and_then(decode_token) {
+            return Ok(CsrfToken(token));
+        }
+
+        if session.is_new() {
+            let cookie = req
+                .extension::<CsrfCookie>()
+                .ok_or_else(|| ApplicationError::SessionError("the CSRF middleware is not installed".to_string()))?;
+            return Ok(CsrfToken(cookie.token()));
+        }
+
+        let token = generate_csrf_token();
+        session.insert(SESSION_KEY, &token)?;
+        Ok(CsrfToken(decode_token(&token).unwrap_or_default()))
+    }
+}
+
+// The token from the request's CSRF cookie, or the one issued in its place
+// while handling the request
+#[derive(Clone)]
+struct CsrfCookie {
+    state: Arc<Mutex<CsrfCookieState>>,
+}
+
+struct CsrfCookieState {
+    token: Option<Vec<u8>>,
+    issued: bool,
+}
+
+impl CsrfCookie {
+    fn new(token: Option<Vec<u8>>) -> Self {
+        Self { state: Arc::new(Mutex::new(CsrfCookieState { token, issued: false })) }
+    }
+
+    fn existing(&self) -> Option<Vec<u8>> {
+        self.state().token.clone()
+    }
+
+    fn token(&self) -> Vec<u8> {
+        let mut state = self.state();
+        if state.token.is_none() {
+            state.token = decode_token(&generate_csrf_token());
+            state.issued = true;
+        }
+        state.token.clone().unwrap_or_default()
+    }
+
+    // A token that still has to be sent to the client
+    fn issued(&self) -> Option<Vec<u8>> {
+        let state = self.state();
+        state.token.clone().filter(|_| state.issued)
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, CsrfCookieState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Synchronizer-token CSRF protection: a POST, PUT, PATCH or DELETE has to
+// carry the visitor's token, as the `csrf_token` form field or an
+// X-CSRF-Token header. Another site can make a browser send the cookies, but
+// it cannot read the token to go with them.
+//
+// Until there is a session the token is a signed double-submit cookie; the
+// signature keeps anyone who can plant cookies from choosing its value.
+//
+// Layered onto the route groups whose forms post back, after the session
+// middleware, so it only runs once a route has matched; the JSON API
+// authenticates with keys and tokens rather than cookies and has nothing to
+// forge.
+pub struct Csrf {
+    key: CookieKey,
+    secure_cookie: bool,
+}
+
+impl Csrf {
+    pub fn new(key: CookieKey) -> Self {
+        Self { key, secure_cookie: false }
+    }
+
+    pub fn with_secure_cookie(mut self, secure_cookie: bool) -> Self {
+        self.secure_cookie = secure_cookie;
+        self
+    }
+
+    fn cookie(&self, token: &[u8]) -> Cookie {
+        self.key
+            .sign(Cookie::new(COOKIE_NAME, &URL_SAFE_NO_PAD.encode(token)))
+            .with_path("/")
+            .with_http_only(true)
+            .with_same_site(SameSite::Lax)
+            .with_secure(self.secure_cookie)
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for Csrf {
+    async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, ApplicationError> {
+        let cookie = CsrfCookie::new(req.cookies().get_signed(&self.key, COOKIE_NAME).as_deref().and_then(decode_token));
+
+        let safe_method = matches!(
+            req.method(),
+            HttpMethod::GET | HttpMethod::HEAD | HttpMethod::OPTIONS | HttpMethod::TRACE
+        );
+        if !safe_method {
+            let expected = Session::from_request(&req)?
+                .get::<String>(SESSION_KEY)
+                .as_deref()
+                .and_then(decode_token)
+                .or_else(|| cookie.existing());
+            let presented = presented_token(&req).as_deref().and_then(unmask);
+
+            let valid = matches!((expected, presented), (Some(expected), Some(presented)) if constant_time_eq(&expected, &presented));
+            if !valid {
+                return Ok(Response::from_error(&ApplicationError::RequestRejected {
+                    status: 403,
+                    message: "Missing or invalid CSRF token; reload the page and try again".to_string(),
+                }));
+            }
+        }
+
+        let response = next.run(req.with_extension(cookie.clone())).await?;
+
+        match cookie.issued() {
+            Some(token) => Ok(response.with_cookie(&self.cookie(&token))),
+            None => Ok(response),
+        }
+    }
+}
+
+fn presented_token(req: &Request) -> Option<String> {
+    if let Some(token) = req.headers().get(CSRF_HEADER) {
+        return Some(token.to_string());
+    }
+
+    let is_form = req
+        .headers()
+        .get("content-type")
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/x-www-form-urlencoded"));
+    if !is_form {
+        return None;
+    }
+
+    let body = std::str::from_utf8(req.body()).ok()?;
+    parse_urlencoded(body)
+        .ok()?
+        .into_iter()
+        .find(|(name, _)| name == CSRF_FIELD)
+        .map(|(_, value)| value)
+}
+
+// Takes as long for a near miss as for a wrong first byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn generate_csrf_token() -> String {
+    let mut bytes = [0; CSRF_TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn decode_token(token: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(token).ok().filter(|bytes| bytes.len() == CSRF_TOKEN_BYTES)
+}
+
+// The token goes out XORed with a random pad and preceded by the pad itself.
+// Compressed pages reflect user input next to it, and a token that stayed the
+// same from one response to the next could be guessed byte by byte from the
+// compressed sizes (BREACH); masked, it never repeats.
+fn mask(token: &[u8]) -> String {
+    let mut pad = [0; CSRF_TOKEN_BYTES];
+    OsRng.fill_bytes(&mut pad);
+
+    let mut masked = pad.to_vec();
+    masked.extend(token.iter().zip(pad).map(|(byte, pad)| byte ^ pad));
+    URL_SAFE_NO_PAD.encode(masked)
+}
+
+fn unmask(masked: &str) -> Option<Vec<u8>> {
+    let bytes = URL_SAFE_NO_PAD.decode(masked).ok().filter(|bytes| bytes.len() == 2 * CSRF_TOKEN_BYTES)?;
+    let (pad, token) = bytes.split_at(CSRF_TOKEN_BYTES);
+    Some(token.iter().zip(pad).map(|(byte, pad)| byte ^ pad).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use super::*;
+    use crate::middleware::session::{SessionConfig, Sessions};
+    use crate::models::session::SessionRecord;
+    use crate::server::route::Route;
+    use crate::server::router::Router;
+    use crate::server::version::HttpVersion;
+    use crate::services::session_store::{MemorySessionStore, SessionStore};
+
+    // Answers with a masked token, as a form page would
+    struct FormPage;
+
+    #[async_trait::async_trait]
+    impl Route for FormPage {
+        async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+            Ok(Response::new(200, "OK").with_text_body(&CsrfToken::from_request(&req)?.value()))
+        }
+    }
+
+    fn form_router(store: Arc<MemorySessionStore>, key: CookieKey) -> Router {
+        let mut router = Router::new();
+        let mut forms = router
+            .group()
+            .layer(Arc::new(Sessions::new(store, SessionConfig::default())))
+            .layer(Arc::new(Csrf::new(key)));
+        forms.add_route(HttpMethod::GET, "/form", Arc::new(FormPage)).unwrap();
+        forms.add_route(HttpMethod::POST, "/form", Arc::new(FormPage)).unwrap();
+        router
+    }
+
+    struct Sent {
+        status: u16,
+        csrf_cookie: Option<String>,
+        body: String,
+    }
+
+    async fn send(router: &Router, raw: &str) -> Sent {
+        let response = router.route(Request::try_from(raw.as_bytes()).unwrap()).await.unwrap();
+        let status = response.status_code();
+        // Just the name=value pair, ready to be sent back
+        let csrf_cookie = response
+            .headers()
+            .get_all("set-cookie")
+            .find(|cookie| cookie.starts_with("csrf="))
+            .and_then(|cookie| cookie.split(';').next())
+            .map(str::to_string);
+
+        let mut written = Vec::new();
+        response.write_to(&mut written, HttpVersion::Http11).await.unwrap();
+        let written = String::from_utf8(written).unwrap();
+        let body = written.split_once("\r\n\r\n").unwrap().1.to_string();
+
+        Sent { status, csrf_cookie, body }
+    }
+
+    async fn stored_sessions(store: &MemorySessionStore) -> u64 {
+        store.delete_expired(Utc::now() + Duration::days(365)).await.unwrap()
+    }
+
+    fn post(cookie: &str, token: &str) -> String {
+        let body = format!("{}={}", CSRF_FIELD, token);
+        format!(
+            "POST /form HTTP/1.1\r\nCookie: {}\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+            cookie,
+            body.len(),
+            body
+        )
+    }
+
+    #[tokio::test]
+    async fn anonymous_visitors_get_a_signed_cookie_instead_of_a_session() {
+        let store = Arc::new(MemorySessionStore::new());
+        let router = form_router(Arc::clone(&store), CookieKey::generate());
+
+        let first = send(&router, "GET /form HTTP/1.1\r\n\r\n").await;
+        let cookie = first.csrf_cookie.unwrap();
+        assert_eq!(stored_sessions(&store).await, 0);
+
+        // The same cookie keeps the same token, masked differently each time
+        let second = send(&router, &format!("GET /form HTTP/1.1\r\nCookie: {}\r\n\r\n", cookie)).await;
+        assert!(second.csrf_cookie.is_none());
+        assert_ne!(first.body, second.body);
+        assert_eq!(unmask(&first.body), unmask(&second.body));
+
+        assert_eq!(send(&router, &post(&cookie, &first.body)).await.status, 200);
+        assert_eq!(send(&router, &post(&cookie, &second.body)).await.status, 200);
+        assert_eq!(stored_sessions(&store).await, 0);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_token_in_an_existing_session() {
+        let store = Arc::new(MemorySessionStore::new());
+        let record = SessionRecord { data: Default::default(), created_at: Utc::now(), expires_at: Utc::now() + Duration::hours(1) };
+        store.save("existing", &record).await.unwrap();
+        let router = form_router(Arc::clone(&store), CookieKey::generate());
+
+        let page = send(&router, "GET /form HTTP/1.1\r\nCookie: session=existing\r\n\r\n").await;
+        assert!(page.csrf_cookie.is_none());
+        assert!(store.load("existing").await.unwrap().unwrap().data.contains_key(SESSION_KEY));
+
+        assert_eq!(send(&router, &post("session=existing", &page.body)).await.status, 200);
+    }
+
+    #[tokio::test]
+    async fn rejects_posts_without_a_matching_token() {
+        let router = form_router(Arc::new(MemorySessionStore::new()), CookieKey::generate());
+
+        let mine = send(&router, "GET /form HTTP/1.1\r\n\r\n").await;
+        let cookie = mine.csrf_cookie.unwrap();
+        let theirs = send(&router, "GET /form HTTP/1.1\r\n\r\n").await;
+
+        assert_eq!(send(&router, &post(&cookie, "")).await.status, 403);
+        assert_eq!(send(&router, &post(&cookie, &theirs.body)).await.status, 403);
+        assert_eq!(send(&router, &post(&theirs.csrf_cookie.unwrap(), &mine.body)).await.status, 403);
+        assert_eq!(send(&router, &post("", &mine.body)).await.status, 403);
+
+        // Cookies planted without the key are ignored
+        let token = unmask(&mine.body).unwrap();
+        let unsigned = format!("{}={}", COOKIE_NAME, URL_SAFE_NO_PAD.encode(&token));
+        assert_eq!(send(&router, &post(&unsigned, &mine.body)).await.status, 403);
+        let other_key = Csrf::new(CookieKey::generate()).cookie(&token);
+        let foreign = format!("{}={}", COOKIE_NAME, other_key.value());
+        assert_eq!(send(&router, &post(&foreign, &mine.body)).await.status, 403);
+    }
+
+    #[test]
+    fn masked_token_differs_every_time_and_unmasks() {
+        let token = decode_token(&generate_csrf_token()).unwrap();
+        let first = mask(&token);
+        let second = mask(&token);
+
+        assert_ne!(first, second);
+        assert_eq!(unmask(&first), Some(token.clone()));
+        assert_eq!(unmask(&second), Some(token));
+    }
+
+    #[test]
+    fn rejects_unmasked_and_malformed_tokens() {
+        let token = generate_csrf_token();
+
+        assert_eq!(unmask(&token), None);
+        assert_eq!(unmask("not base64!"), None);
+        assert_eq!(unmask(""), None);
+        assert_eq!(decode_token("c2hvcnQ"), None);
+    }
+
+    #[test]
+    fn tampered_token_does_not_verify() {
+        let token = decode_token(&generate_csrf_token()).unwrap();
+        let mut masked = URL_SAFE_NO_PAD.decode(mask(&token)).unwrap();
+        masked[CSRF_TOKEN_BYTES] ^= 1;
+
+        let unmasked = unmask(&URL_SAFE_NO_PAD.encode(masked)).unwrap();
+        assert!(!constant_time_eq(&token, &unmasked));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::server::middleware::{Middleware, Next};
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::server::urlencoded::percent_encode;
+use crate::utils::error::ApplicationError;
+
+// Answers every request on the plain HTTP listener with a permanent redirect
+// to the same URL over HTTPS. 308 rather than 301 so a POST stays a POST.
+pub struct HttpsRedirect {
+    https_port: u16,
+}
+
+impl HttpsRedirect {
+    pub fn new(https_port: u16) -> Self {
+        Self { https_port }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for HttpsRedirect {
+    async fn handle(&self, req: Request, _next: Next<'_>) -> Result<Response, ApplicationError> {
+        let Some(host) = req.headers().get("host").and_then(host_name) else {
+            return Err(ApplicationError::RequestRejected { status: 400, message: "Missing or invalid Host header".to_string() });
+        };
+
+        let authority = match self.https_port {
+            443 => host.to_string(),
+            port => format!("{}:{}", host, port),
+        };
+
+        // The path was decoded when the request was parsed
+        let path = req.path().split('/').map(percent_encode).collect::<Vec<_>>().join("/");
+        let query = req
+            .query_params()
+            .iter()
+            .map(|(name, value)| format!("{}={}", percent_encode(name), percent_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let location = if query.is_empty() {
+            format!("https://{}{}", authority, path)
+        } else {
+            format!("https://{}{}?{}", authority, path, query)
+        };
+
+        Ok(Response::new(308, "Permanent Redirect").with_header("Location", &location))
+    }
+}
+
+// The host part of a Host header, without the port. Only characters that can
+// appear in a host name or an IP literal are accepted, so the header cannot
+// steer the redirect anywhere odd.
+fn host_name(host: &str) -> Option<&str> {
+    let name = if host.starts_with('[') {
+        &host[..=host.find(']')?]
+    } else {
+        host.split(':').next()?
+    };
+
+    let valid = !name.is_empty()
+        && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"-.[]:".contains(&byte));
+    valid.then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+    use crate::server::router::Router;
+
+    #[test]
+    fn takes_the_host_name_without_the_port() {
+        assert_eq!(host_name("example.com"), Some("example.com"));
+        assert_eq!(host_name("example.com:8080"), Some("example.com"));
+        assert_eq!(host_name("127.0.0.1:80"), Some("127.0.0.1"));
+        assert_eq!(host_name("[::1]:8080"), Some("[::1]"));
+        assert_eq!(host_name("[::1]"), Some("[::1]"));
+    }
+
+    #[test]
+    fn rejects_hosts_that_could_steer_the_redirect() {
+        for host in ["", ":8080", "[::1", "evil.com/path", "evil.com@example.com", "example.com\\evil", "exa mple.com", "example.com?x"] {
+            assert_eq!(host_name(host), None, "accepted {:?}", host);
+        }
+    }
+
+    async fn redirect(https_port: u16, raw: &str) -> Response {
+        let mut router = Router::new();
+        router.layer(Arc::new(HttpsRedirect::new(https_port)));
+        router.route(Request::try_from(raw.as_bytes()).unwrap()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn redirects_to_the_same_url_over_https() {
+        let response = redirect(443, "POST /a%20b/c?q=x%26y HTTP/1.1\r\nHost: example.com:80\r\n\r\n").await;
+        assert_eq!(response.status_code(), 308);
+        assert_eq!(response.header("Location"), Some("https://example.com/a%20b/c?q=x%26y"));
+
+        let response = redirect(8443, "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").await;
+        assert_eq!(response.header("Location"), Some("https://example.com:8443/"));
+    }
+
+    #[tokio::test]
+    async fn refuses_requests_without_a_usable_host() {
+        let mut router = Router::new();
+        router.layer(Arc::new(HttpsRedirect::new(443)));
+
+        for raw in ["GET / HTTP/1.1\r\n\r\n", "GET / HTTP/1.1\r\nHost: evil.com/x\r\n\r\n"] {
+            let result = router.route(Request::try_from(raw.as_bytes()).unwrap()).await;
+            assert!(matches!(result, Err(ApplicationError::RequestRejected { status: 400, .. })), "redirected {:?}", raw);
+        }
+    }
+}
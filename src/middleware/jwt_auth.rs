@@ -0,0 +1,68 @@
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::server::extract::FromRequest;
+use crate::server::middleware::{Middleware, Next};
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::services::jwt::{Claims, JwtVerifier};
+use crate::utils::error::ApplicationError;
+
+const REALM: &str = "api";
+
+// Handlers behind `JwtAuth` read the verified claims with
+// `Claims::from_request(&req)?`
+impl FromRequest for Claims {
+    fn from_request(req: &Request) -> Result<Self, ApplicationError> {
+        req.extension::<Claims>().cloned().ok_or_else(|| ApplicationError::RequestRejected {
+            status: 401,
+            message: "Bearer token required".to_string(),
+        })
+    }
+}
+
+// Requires `Authorization: Bearer <jwt>` with a token `JwtVerifier` accepts.
+// The reason a token was refused is logged but not sent back, so callers
+// cannot probe which check failed.
+pub struct JwtAuth {
+    verifier: Arc<JwtVerifier>,
+}
+
+impl JwtAuth {
+    pub fn new(verifier: Arc<JwtVerifier>) -> Self {
+        Self { verifier }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for JwtAuth {
+    async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, ApplicationError> {
+        let Some(token) = bearer_token(&req) else {
+            return Ok(challenge("Bearer token required", &format!("Bearer realm=\"{}\"", REALM)));
+        };
+
+        let claims = match self.verifier.verify(token) {
+            Ok(claims) => claims,
+            Err(e) => {
+                debug!("Rejected bearer token for {}: {}", req.path(), e);
+                let description = "The token is invalid or expired";
+                return Ok(challenge(
+                    description,
+                    &format!("Bearer realm=\"{}\", error=\"invalid_token\", error_description=\"{}\"", REALM, description),
+                ));
+            }
+        };
+
+        next.run(req.with_extension(claims)).await
+    }
+}
+
+fn bearer_token(req: &Request) -> Option<&str> {
+    let (scheme, token) = req.headers().get("authorization")?.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+fn challenge(message: &str, www_authenticate: &str) -> Response {
+    let rejection = ApplicationError::RequestRejected { status: 401, message: message.to_string() };
+    Response::from_error(&rejection).with_header("WWW-Authenticate", www_authenticate)
+}
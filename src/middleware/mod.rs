@@ -0,0 +1,11 @@
+pub mod access_log;
+pub mod session;
+pub mod require_login;
+pub mod api_key_auth;
+pub mod jwt_auth;
+pub mod rate_limit;
+pub mod cors;
+pub mod security_headers;
+pub mod csrf;
+pub mod https_redirect;
+pub mod compression;
\ No newline at end of file
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tokio::time;
+use tracing::info;
+
+use crate::middleware::api_key_auth::AuthenticatedKey;
+use crate::middleware::require_login::USER_ID_KEY;
+use crate::middleware::session::Session;
+use crate::server::config::env_or;
+use crate::server::middleware::{Middleware, Next};
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::utils::error::ApplicationError;
+
+// Who a bucket belongs to. Requests without an API key or a logged-in user
+// fall back to the peer IP, so nothing goes unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitKey {
+    PeerIp,
+    // Needs `ApiKeyAuth` earlier in the chain
+    ApiKey,
+    // Needs the session middleware earlier in the chain
+    User,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+    // How many requests may arrive at once...
+    pub burst: u32,
+    // ...and how quickly that allowance comes back
+    pub per_minute: u32,
+}
+
+impl RateLimitConfig {
+    // Reads `<prefix>_BURST` and `<prefix>_PER_MINUTE`, e.g. RATE_LIMIT_API_BURST
+    pub fn from_env(prefix: &str, default: Self) -> Result<Self, ApplicationError> {
+        let config = Self {
+            burst: env_or(&format!("{}_BURST", prefix), default.burst),
+            per_minute: env_or(&format!("{}_PER_MINUTE", prefix), default.per_minute),
+        };
+
+        if config.burst == 0 || config.per_minute == 0 {
+            return Err(ApplicationError::InvalidEnvVar(format!("{}_*: limits must be greater than zero", prefix)));
+        }
+
+        Ok(config)
+    }
+
+    fn tokens_per_second(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    // The tokens the bucket would hold at `now`, capped at the burst size
+    fn refilled(&self, config: &RateLimitConfig, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * config.tokens_per_second()).min(f64::from(config.burst))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Decision {
+    Allowed { remaining: u32, reset: u64 },
+    Limited { retry_after: u64 },
+}
+
+// Token-bucket rate limiting: every client gets `burst` requests up front,
+// refilled at `per_minute`. Each middleware instance keeps its own buckets,
+// so every route group it is layered on gets a separate allowance. Responses
+// carry the RateLimit-* headers from the IETF draft; rejected requests get a
+// 429 with Retry-After.
+pub struct RateLimit {
+    config: RateLimitConfig,
+    key: RateLimitKey,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimit {
+    pub fn new(config: RateLimitConfig, key: RateLimitKey) -> Self {
+        Self { config, key, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    // Drops buckets that have refilled completely. They are no different
+    // from the fresh bucket a returning client would get, so this bounds the
+    // memory used without letting anyone off early.
+    pub fn evict_idle(&self) -> usize {
+        self.evict_idle_at(Instant::now())
+    }
+
+    fn evict_idle_at(&self, now: Instant) -> usize {
+        let burst = f64::from(self.config.burst);
+        let mut buckets = self.buckets();
+
+        let before = buckets.len();
+        buckets.retain(|_, bucket| bucket.refilled(&self.config, now) < burst);
+        before - buckets.len()
+    }
+
+    fn client_key(&self, req: &Request) -> String {
+        let key = match self.key {
+            RateLimitKey::PeerIp => None,
+            RateLimitKey::ApiKey => req.extension::<AuthenticatedKey>().map(|key| format!("key:{}", key.0.id)),
+            RateLimitKey::User => req
+                .extension::<Session>()
+                .and_then(|session| session.get::<i64>(USER_ID_KEY))
+                .map(|user_id| format!("user:{}", user_id)),
+        };
+
+        key.unwrap_or_else(|| match req.peer_addr() {
+            Some(addr) => format!("ip:{}", addr.ip()),
+            None => "ip:unknown".to_string(),
+        })
+    }
+
+    fn take_token(&self, client: String, now: Instant) -> Decision {
+        let rate = self.config.tokens_per_second();
+        let burst = f64::from(self.config.burst);
+
+        let mut buckets = self.buckets();
+        let bucket = buckets.entry(client).or_insert(Bucket { tokens: burst, updated: now });
+        bucket.tokens = bucket.refilled(&self.config, now);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return Decision::Limited { retry_after: ((1.0 - bucket.tokens) / rate).ceil() as u64 };
+        }
+
+        bucket.tokens -= 1.0;
+        Decision::Allowed {
+            remaining: bucket.tokens.floor() as u32,
+            reset: ((burst - bucket.tokens) / rate).ceil() as u64,
+        }
+    }
+
+    fn buckets(&self) -> MutexGuard<'_, HashMap<String, Bucket>> {
+        self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for RateLimit {
+    async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, ApplicationError> {
+        let limit = self.config.burst.to_string();
+
+        match self.take_token(self.client_key(&req), Instant::now()) {
+            Decision::Allowed { remaining, reset } => {
+                let response = next.run(req).await?;
+
+                // A limiter further in is the more specific one, such as a
+                // per-key limit behind a per-IP one, so its headers stay
+                if response.header("RateLimit-Limit").is_some() {
+                    return Ok(response);
+                }
+
+                Ok(response
+                    .with_header("RateLimit-Limit", &limit)
+                    .with_header("RateLimit-Remaining", &remaining.to_string())
+                    .with_header("RateLimit-Reset", &reset.to_string()))
+            }
+            Decision::Limited { retry_after } => {
+                let rejection = ApplicationError::RequestRejected {
+                    status: 429,
+                    message: format!("Too many requests, retry in {} seconds", retry_after),
+                };
+                Ok(Response::from_error(&rejection)
+                    .with_header("Retry-After", &retry_after.to_string())
+                    .with_header("RateLimit-Limit", &limit)
+                    .with_header("RateLimit-Remaining", "0")
+                    .with_header("RateLimit-Reset", &retry_after.to_string()))
+            }
+        }
+    }
+}
+
+// Periodically evicts idle buckets from each of `limits`
+pub fn spawn_rate_limit_sweeper(limits: Vec<Arc<RateLimit>>, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            let evicted: usize = limits.iter().map(|limit| limit.evict_idle()).sum();
+            if evicted > 0 {
+                info!("Evicted {} idle rate limit buckets", evicted);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::methods::HttpMethod;
+    use crate::server::route::Route;
+    use crate::server::router::Router;
+
+    fn limit(burst: u32, per_minute: u32) -> RateLimit {
+        RateLimit::new(RateLimitConfig { burst, per_minute }, RateLimitKey::PeerIp)
+    }
+
+    fn seconds(seconds: f64) -> Duration {
+        Duration::from_secs_f64(seconds)
+    }
+
+    #[test]
+    fn spends_the_burst_then_refills_at_the_rate() {
+        let limit = limit(3, 60);
+        let start = Instant::now();
+        let take = |at: f64| limit.take_token("client".to_string(), start + seconds(at));
+
+        assert_eq!(take(0.0), Decision::Allowed { remaining: 2, reset: 1 });
+        assert_eq!(take(0.0), Decision::Allowed { remaining: 1, reset: 2 });
+        assert_eq!(take(0.0), Decision::Allowed { remaining: 0, reset: 3 });
+        assert_eq!(take(0.0), Decision::Limited { retry_after: 1 });
+
+        // Half a token is not enough, and the wait rounds up
+        assert_eq!(take(0.5), Decision::Limited { retry_after: 1 });
+        assert_eq!(take(1.0), Decision::Allowed { remaining: 0, reset: 3 });
+
+        // However long the client stays away, the bucket holds no more than the burst
+        assert_eq!(take(1000.0), Decision::Allowed { remaining: 2, reset: 1 });
+    }
+
+    #[test]
+    fn retry_after_follows_a_slow_rate() {
+        let limit = limit(1, 6);
+        let start = Instant::now();
+
+        assert_eq!(limit.take_token("client".to_string(), start), Decision::Allowed { remaining: 0, reset: 10 });
+        assert_eq!(limit.take_token("client".to_string(), start), Decision::Limited { retry_after: 10 });
+        assert_eq!(limit.take_token("client".to_string(), start + seconds(7.5)), Decision::Limited { retry_after: 3 });
+        assert_eq!(limit.take_token("client".to_string(), start + seconds(10.0)), Decision::Allowed { remaining: 0, reset: 10 });
+    }
+
+    #[test]
+    fn clients_have_separate_buckets() {
+        let limit = limit(1, 60);
+        let now = Instant::now();
+
+        assert!(matches!(limit.take_token("ip:10.0.0.1".to_string(), now), Decision::Allowed { .. }));
+        assert!(matches!(limit.take_token("ip:10.0.0.1".to_string(), now), Decision::Limited { .. }));
+        assert!(matches!(limit.take_token("ip:10.0.0.2".to_string(), now), Decision::Allowed { .. }));
+    }
+
+    #[test]
+    fn evicts_only_buckets_that_refilled() {
+        let limit = limit(3, 60);
+        let start = Instant::now();
+        limit.take_token("busy".to_string(), start);
+        limit.take_token("busy".to_string(), start);
+        limit.take_token("quiet".to_string(), start);
+
+        assert_eq!(limit.evict_idle_at(start + seconds(0.5)), 0);
+        assert_eq!(limit.evict_idle_at(start + seconds(1.0)), 1);
+        assert_eq!(limit.buckets().keys().collect::<Vec<_>>(), ["busy"]);
+        assert_eq!(limit.evict_idle_at(start + seconds(2.0)), 1);
+        assert!(limit.buckets().is_empty());
+    }
+
+    struct Ok200;
+
+    #[async_trait::async_trait]
+    impl Route for Ok200 {
+        async fn handle(&self, _req: Request) -> Result<Response, ApplicationError> {
+            Ok(Response::new(200, "OK"))
+        }
+    }
+
+    async fn get(router: &Router) -> Response {
+        router.route(Request::try_from(&b"GET / HTTP/1.1\r\n\r\n"[..]).unwrap()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn answers_429_with_retry_after() {
+        let mut router = Router::new();
+        router.layer(Arc::new(limit(1, 60)));
+        router.add_route(HttpMethod::GET, "/", Arc::new(Ok200)).unwrap();
+
+        let allowed = get(&router).await;
+        assert_eq!(allowed.status_code(), 200);
+        assert_eq!(allowed.header("RateLimit-Limit"), Some("1"));
+        assert_eq!(allowed.header("RateLimit-Remaining"), Some("0"));
+
+        let limited = get(&router).await;
+        assert_eq!(limited.status_code(), 429);
+        assert_eq!(limited.header("Retry-After"), Some("1"));
+        assert_eq!(limited.header("RateLimit-Remaining"), Some("0"));
+    }
+
+    #[tokio::test]
+    async fn keeps_the_headers_of_the_inner_limit() {
+        let mut router = Router::new();
+        router.layer(Arc::new(limit(10, 60)));
+        router.layer(Arc::new(limit(2, 60)));
+        router.add_route(HttpMethod::GET, "/", Arc::new(Ok200)).unwrap();
+
+        assert_eq!(get(&router).await.header("RateLimit-Limit"), Some("2"));
+    }
+}
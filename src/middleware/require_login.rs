@@ -0,0 +1,169 @@
+use std::sync::Arc;
+
+use crate::middleware::session::Session;
+use crate::models::user::User;
+use crate::server::extract::FromRequest;
+use crate::server::methods::HttpMethod;
+use crate::server::middleware::{Middleware, Next};
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::server::urlencoded::percent_encode;
+use crate::services::database::Database;
+use crate::utils::error::ApplicationError;
+
+// Session key under which the login routes store the ID of the logged-in user
+pub const USER_ID_KEY: &str = "user_id";
+
+// The logged-in user, put in the request extensions by `RequireLogin`
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub User);
+
+impl FromRequest for CurrentUser {
+    fn from_request(req: &Request) -> Result<Self, ApplicationError> {
+        req.extension::<CurrentUser>().cloned().ok_or_else(login_required)
+    }
+}
+
+// Lets a request through only when its session belongs to a user who still
+// exists. Browsers asking for a page are sent to the login form and brought
+// back afterwards; anything else gets a 401.
+pub struct RequireLogin {
+    database: Arc<Database>,
+}
+
+impl RequireLogin {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+
+    async fn current_user(&self, session: &Session) -> Result<Option<User>, ApplicationError> {
+        let Some(user_id) = session.get::<i64>(USER_ID_KEY) else {
+            return Ok(None);
+        };
+
+        let user = self.database.get_user_by_id(user_id).await?;
+        if user.is_none() {
+            // The account was deleted while this session was logged in
+            session.remove(USER_ID_KEY);
+        }
+
+        Ok(user)
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for RequireLogin {
+    async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, ApplicationError> {
+        let session = Session::from_request(&req)?;
+
+        if let Some(user) = self.current_user(&session).await? {
+            return next.run(req.with_extension(CurrentUser(user))).await;
+        }
+
+        let wants_page = matches!(req.method(), HttpMethod::GET | HttpMethod::HEAD)
+            && req.headers().get("accept").is_some_and(|accept| accept.contains("text/html"));
+
+        if wants_page {
+            Ok(Response::redirect(&format!("/login?next={}", percent_encode(req.path()))))
+        } else {
+            Ok(Response::from_error(&login_required()))
+        }
+    }
+}
+
+fn login_required() -> ApplicationError {
+    ApplicationError::RequestRejected { status: 401, message: "Login required".to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use chrono::{Duration, Utc};
+    use serde_json::Value;
+    use super::*;
+    use crate::middleware::session::{SessionConfig, Sessions};
+    use crate::models::session::SessionRecord;
+    use crate::server::route::Route;
+    use crate::server::router::Router;
+    use crate::services::session_store::{MemorySessionStore, SessionStore};
+
+    struct Greeting;
+
+    #[async_trait::async_trait]
+    impl Route for Greeting {
+        async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+            let CurrentUser(user) = CurrentUser::from_request(&req)?;
+            Ok(Response::new(200, "OK").with_text_body(&format!("hello {}", user.username)))
+        }
+    }
+
+    async fn setup() -> (Router, Arc<MemorySessionStore>, Arc<Database>) {
+        let database = Arc::new(Database::new("sqlite::memory:".to_string()).await.unwrap());
+        let store = Arc::new(MemorySessionStore::new());
+
+        let mut router = Router::new();
+        let mut account = router
+            .group()
+            .layer(Arc::new(Sessions::new(Arc::clone(&store) as Arc<dyn SessionStore>, SessionConfig::default())))
+            .layer(Arc::new(RequireLogin::new(Arc::clone(&database))));
+        account.add_route(HttpMethod::GET, "/account/settings", Arc::new(Greeting)).unwrap();
+        account.add_route(HttpMethod::POST, "/account/settings", Arc::new(Greeting)).unwrap();
+
+        (router, store, database)
+    }
+
+    async fn log_in(store: &MemorySessionStore, session_id: &str, user_id: i64) {
+        let now = Utc::now();
+        let data = HashMap::from([(USER_ID_KEY.to_string(), Value::from(user_id))]);
+        store.save(session_id, &SessionRecord { data, created_at: now, expires_at: now + Duration::hours(1) }).await.unwrap();
+    }
+
+    async fn send(router: &Router, raw: &str) -> Response {
+        router.route(Request::try_from(raw.as_bytes()).unwrap()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_browsers_to_the_login_form_and_back() {
+        let (router, _, _) = setup().await;
+
+        let response = send(&router, "GET /account/settings?tab=keys HTTP/1.1\r\nAccept: text/html,*/*\r\n\r\n").await;
+        assert_eq!(response.status_code(), 303);
+        assert_eq!(response.header("Location"), Some("/login?next=%2Faccount%2Fsettings"));
+    }
+
+    #[tokio::test]
+    async fn answers_401_to_everything_else() {
+        let (router, _, _) = setup().await;
+
+        for raw in [
+            "GET /account/settings HTTP/1.1\r\nAccept: application/json\r\n\r\n",
+            "GET /account/settings HTTP/1.1\r\n\r\n",
+            "POST /account/settings HTTP/1.1\r\nAccept: text/html\r\n\r\n",
+        ] {
+            assert_eq!(send(&router, raw).await.status_code(), 401, "for {:?}", raw);
+        }
+    }
+
+    #[tokio::test]
+    async fn lets_existing_users_through() {
+        let (router, store, database) = setup().await;
+        let user = database.create_user("alice", "$argon2id$placeholder").await.unwrap().unwrap();
+        log_in(&store, "alice-session", user.id).await;
+
+        let response = send(&router, "GET /account/settings HTTP/1.1\r\nCookie: session=alice-session\r\n\r\n").await;
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.content_length(), Some("hello alice".len() as u64));
+    }
+
+    #[tokio::test]
+    async fn logs_out_sessions_of_deleted_users() {
+        let (router, store, _) = setup().await;
+        log_in(&store, "ghost-session", 999).await;
+
+        let response = send(&router, "GET /account/settings HTTP/1.1\r\nCookie: session=ghost-session\r\n\r\n").await;
+        assert_eq!(response.status_code(), 401);
+
+        let record = store.load("ghost-session").await.unwrap().unwrap();
+        assert!(!record.data.contains_key(USER_ID_KEY));
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::models::session::SessionRecord;
+use crate::server::config::env_or;
+use crate::server::cookie::{Cookie, CookieKey, SameSite};
+use crate::server::extract::FromRequest;
+use crate::server::middleware::{Middleware, Next};
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::services::session_store::SessionStore;
+use crate::utils::error::ApplicationError;
+
+const SESSION_ID_BYTES: usize = 32;
+
+const DEFAULT_COOKIE_NAME: &str = "session";
+const DEFAULT_IDLE_TIMEOUT: u64 = 30 * 60;
+const DEFAULT_ABSOLUTE_TIMEOUT: u64 = 24 * 60 * 60;
+const DEFAULT_SWEEP_INTERVAL: u64 = 10 * 60;
+
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    pub cookie_name: String,
+    // A session ends after this long without a request...
+    pub idle_timeout: Duration,
+    // ...and after this long in any case
+    pub absolute_timeout: Duration,
+    pub secure_cookie: bool,
+    pub sweep_interval: Duration,
+}
+
+impl SessionConfig {
+    pub fn from_env() -> Self {
+        Self {
+            cookie_name: env_or("SESSION_COOKIE_NAME", DEFAULT_COOKIE_NAME.to_string()),
+            idle_timeout: Duration::from_secs(env_or("SESSION_IDLE_TIMEOUT", DEFAULT_IDLE_TIMEOUT)),
+            absolute_timeout: Duration::from_secs(env_or("SESSION_ABSOLUTE_TIMEOUT", DEFAULT_ABSOLUTE_TIMEOUT)),
+            secure_cookie: env_or("SESSION_COOKIE_SECURE", false),
+            sweep_interval: Duration::from_secs(env_or("SESSION_SWEEP_INTERVAL", DEFAULT_SWEEP_INTERVAL)),
+        }
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            cookie_name: DEFAULT_COOKIE_NAME.to_string(),
+            idle_timeout: Duration::from_secs(DEFAULT_IDLE_TIMEOUT),
+            absolute_timeout: Duration::from_secs(DEFAULT_ABSOLUTE_TIMEOUT),
+            secure_cookie: false,
+            sweep_interval: Duration::from_secs(DEFAULT_SWEEP_INTERVAL),
+        }
+    }
+}
+
+// The current request's session, shared between the handler (which reads
+// and changes it) and the middleware (which saves it afterwards). Values are
+// stored as JSON, so anything serde can handle fits.
+#[derive(Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+struct SessionState {
+    // `None` until the session is first saved
+    id: Option<String>,
+    data: HashMap<String, Value>,
+    created_at: DateTime<Utc>,
+    rotate: bool,
+    destroyed: bool,
+}
+
+impl SessionState {
+    fn new(id: Option<String>, data: HashMap<String, Value>, created_at: DateTime<Utc>) -> Self {
+        Self { id, data, created_at, rotate: false, destroyed: false }
+    }
+}
+
+impl Session {
+    fn new(id: Option<String>, data: HashMap<String, Value>, created_at: DateTime<Utc>) -> Self {
+        Self { state: Arc::new(Mutex::new(SessionState::new(id, data, created_at))) }
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let state = self.state();
+        serde_json::from_value(state.data.get(key)?.clone()).ok()
+    }
+
+    pub fn insert<T: Serialize>(&self, key: &str, value: T) -> Result<(), ApplicationError> {
+        let value = serde_json::to_value(value)
+            .map_err(|e| ApplicationError::SessionError(format!("cannot store '{}': {}", key, e)))?;
+
+        self.state().data.insert(key.to_string(), value);
+        Ok(())
+    }
+
+    // Whether the visitor came without a session, which they keep having
+    // unless something gets stored
+    pub fn is_new(&self) -> bool {
+        self.state().id.is_none()
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.state().data.remove(key);
+    }
+
+    pub fn clear(&self) {
+        self.state().data.clear();
+    }
+
+    // Moves the data to a fresh ID. Call this whenever the session gains
+    // privileges, such as on login, so an ID planted in the browser
+    // beforehand (session fixation) is worth nothing afterwards.
+    pub fn rotate(&self) {
+        self.state().rotate = true;
+    }
+
+    // Ends the session for good and clears the cookie
+    pub fn destroy(&self) {
+        let mut state = self.state();
+        state.data.clear();
+        state.destroyed = true;
+    }
+
+    // Hands the state over to the middleware once the handler is done
+    fn take(&self) -> SessionState {
+        let mut state = self.state();
+        let created_at = state.created_at;
+        std::mem::replace(&mut *state, SessionState::new(None, HashMap::new(), created_at))
+    }
+
+    fn state(&self) -> MutexGuard<'_, SessionState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Handlers behind the session middleware take the session with
+// `Session::from_request(&req)?`
+impl FromRequest for Session {
+    fn from_request(req: &Request) -> Result<Self, ApplicationError> {
+        req.extension::<Session>()
+            .cloned()
+            .ok_or_else(|| ApplicationError::SessionError("the session middleware is not installed".to_string()))
+    }
+}
+
+// Loads the session named by the request's cookie before the handler runs and
+// saves it afterwards. A visitor only gets a session (and a cookie) once
+// something is stored in it.
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    config: SessionConfig,
+    key: Option<CookieKey>,
+}
+
+impl Sessions {
+    pub fn new(store: Arc<dyn SessionStore>, config: SessionConfig) -> Self {
+        Self { store, config, key: None }
+    }
+
+    // Signs the session cookie, so a made-up or altered ID is turned away
+    // without a trip to the store
+    pub fn with_cookie_key(mut self, key: CookieKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    fn session_id(&self, req: &Request) -> Option<String> {
+        match &self.key {
+            Some(key) => req.cookies().get_signed(key, &self.config.cookie_name),
+            None => req.cookies().get(&self.config.cookie_name).map(str::to_string),
+        }
+    }
+
+    async fn load(&self, req: &Request, now: DateTime<Utc>) -> Result<Session, ApplicationError> {
+        let Some(id) = self.session_id(req) else {
+            return Ok(Session::new(None, HashMap::new(), now));
+        };
+
+        match self.store.load(&id).await? {
+            Some(record) if !record.is_expired(now) => Ok(Session::new(Some(id), record.data, record.created_at)),
+            Some(_) => {
+                self.store.delete(&id).await?;
+                Ok(Session::new(None, HashMap::new(), now))
+            }
+            None => Ok(Session::new(None, HashMap::new(), now)),
+        }
+    }
+
+    async fn save(&self, session: &Session, response: Response, now: DateTime<Utc>) -> Result<Response, ApplicationError> {
+        let SessionState { id: old_id, data, created_at, rotate, destroyed } = session.take();
+
+        if destroyed {
+            return match old_id {
+                Some(id) => {
+                    self.store.delete(&id).await?;
+                    Ok(response.with_cookie(&self.cookie(Cookie::removal(&self.config.cookie_name))))
+                }
+                None => Ok(response),
+            };
+        }
+
+        // Nothing worth remembering about this visitor yet
+        if old_id.is_none() && data.is_empty() {
+            return Ok(response);
+        }
+
+        let idle_expiry = now + self.config.idle_timeout;
+        let absolute_expiry = created_at + self.config.absolute_timeout;
+        let record = SessionRecord { data, created_at, expires_at: idle_expiry.min(absolute_expiry) };
+
+        // Every request pushes the idle expiry back, so the record is
+        // written even when its data did not change
+        match old_id {
+            Some(id) if !rotate => {
+                self.store.save(&id, &record).await?;
+                Ok(response)
+            }
+            old_id => {
+                if let Some(id) = old_id {
+                    self.store.delete(&id).await?;
+                }
+
+                let id = generate_session_id();
+                self.store.save(&id, &record).await?;
+
+                let mut cookie = Cookie::new(&self.config.cookie_name, &id).with_max_age(self.config.absolute_timeout);
+                if let Some(key) = &self.key {
+                    cookie = key.sign(cookie);
+                }
+                Ok(response.with_cookie(&self.cookie(cookie)))
+            }
+        }
+    }
+
+    fn cookie(&self, cookie: Cookie) -> Cookie {
+        cookie
+            .with_path("/")
+            .with_http_only(true)
+            .with_same_site(SameSite::Lax)
+            .with_secure(self.config.secure_cookie)
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for Sessions {
+    async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, ApplicationError> {
+        let now = Utc::now();
+        let session = self.load(&req, now).await?;
+
+        let response = next.run(req.with_extension(session.clone())).await?;
+
+        self.save(&session, response, now).await
+    }
+}
+
+// 256 random bits, far beyond what anyone could guess
+fn generate_session_id() -> String {
+    let mut bytes = [0; SESSION_ID_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration as ChronoDuration;
+    use super::*;
+    use crate::services::session_store::MemorySessionStore;
+
+    fn sessions(store: &Arc<MemorySessionStore>, idle_minutes: u64, absolute_minutes: u64) -> Sessions {
+        let config = SessionConfig {
+            idle_timeout: Duration::from_secs(idle_minutes * 60),
+            absolute_timeout: Duration::from_secs(absolute_minutes * 60),
+            ..SessionConfig::default()
+        };
+        Sessions::new(Arc::clone(store) as Arc<dyn SessionStore>, config)
+    }
+
+    // One request through the middleware at `now`, with `handler` standing in
+    // for the route. Returns the Set-Cookie header, if any.
+    async fn request(sessions: &Sessions, cookie: Option<&str>, now: DateTime<Utc>, handler: impl FnOnce(&Session)) -> Option<String> {
+        let raw = match cookie {
+            Some(cookie) => format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", cookie),
+            None => "GET / HTTP/1.1\r\n\r\n".to_string(),
+        };
+        let req = Request::try_from(raw.as_bytes()).unwrap();
+
+        let session = sessions.load(&req, now).await.unwrap();
+        handler(&session);
+        let response = sessions.save(&session, Response::new(200, "OK"), now).await.unwrap();
+        response.header("Set-Cookie").map(str::to_string)
+    }
+
+    // "session=<id>" out of a Set-Cookie header
+    fn cookie_pair(set_cookie: &str) -> String {
+        set_cookie.split(';').next().unwrap().to_string()
+    }
+
+    fn id_of(cookie: &str) -> &str {
+        cookie.split_once('=').unwrap().1
+    }
+
+    fn minutes(minutes: i64) -> ChronoDuration {
+        ChronoDuration::minutes(minutes)
+    }
+
+    #[tokio::test]
+    async fn starts_a_session_only_once_something_is_stored() {
+        let store = Arc::new(MemorySessionStore::new());
+        let sessions = sessions(&store, 30, 24 * 60);
+        let now = Utc::now();
+
+        assert_eq!(request(&sessions, None, now, |_| {}).await, None);
+        assert_eq!(request(&sessions, Some("session=made-up"), now, |_| {}).await, None);
+
+        let set_cookie = request(&sessions, None, now, |session| session.insert("theme", "dark").unwrap()).await.unwrap();
+        assert!(set_cookie.contains("HttpOnly") && set_cookie.contains("SameSite=Lax") && set_cookie.contains("Path=/"));
+
+        let cookie = cookie_pair(&set_cookie);
+        assert!(store.load(id_of(&cookie)).await.unwrap().is_some());
+
+        let mut theme = None;
+        let again = request(&sessions, Some(&cookie), now, |session| theme = session.get::<String>("theme")).await;
+        assert_eq!(theme.as_deref(), Some("dark"));
+        assert_eq!(again, None);
+    }
+
+    #[tokio::test]
+    async fn rotation_moves_the_data_to_a_new_id() {
+        let store = Arc::new(MemorySessionStore::new());
+        let sessions = sessions(&store, 30, 24 * 60);
+        let now = Utc::now();
+
+        let old = cookie_pair(&request(&sessions, None, now, |session| session.insert("cart", 3).unwrap()).await.unwrap());
+        let new = request(&sessions, Some(&old), now, |session| {
+            session.rotate();
+            session.insert("user_id", 42).unwrap();
+        })
+        .await
+        .map(|set_cookie| cookie_pair(&set_cookie))
+        .unwrap();
+
+        assert_ne!(old, new);
+        assert!(store.load(id_of(&old)).await.unwrap().is_none());
+
+        let record = store.load(id_of(&new)).await.unwrap().unwrap();
+        assert_eq!(record.data.get("cart"), Some(&Value::from(3)));
+        assert_eq!(record.data.get("user_id"), Some(&Value::from(42)));
+
+        // The old ID is worth nothing now
+        let mut user_id = None;
+        request(&sessions, Some(&old), now, |session| user_id = session.get::<i64>("user_id")).await;
+        assert_eq!(user_id, None);
+    }
+
+    #[tokio::test]
+    async fn expires_after_the_idle_timeout() {
+        let store = Arc::new(MemorySessionStore::new());
+        let sessions = sessions(&store, 30, 24 * 60);
+        let start = Utc::now();
+
+        let cookie = cookie_pair(&request(&sessions, None, start, |session| session.insert("n", 1).unwrap()).await.unwrap());
+        let read = |at| {
+            let (sessions, cookie) = (&sessions, &cookie);
+            async move {
+                let mut n = None;
+                request(sessions, Some(cookie), at, |session| n = session.get::<i64>("n")).await;
+                n
+            }
+        };
+
+        // Each request pushes the expiry back
+        assert_eq!(read(start + minutes(29)).await, Some(1));
+        assert_eq!(read(start + minutes(58)).await, Some(1));
+        assert_eq!(read(start + minutes(89)).await, None);
+        assert!(store.load(id_of(&cookie)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn expires_after_the_absolute_timeout_however_busy() {
+        let store = Arc::new(MemorySessionStore::new());
+        let sessions = sessions(&store, 30, 60);
+        let start = Utc::now();
+
+        let cookie = cookie_pair(&request(&sessions, None, start, |session| session.insert("n", 1).unwrap()).await.unwrap());
+
+        for minute in [20, 40, 59] {
+            let mut n = None;
+            request(&sessions, Some(&cookie), start + minutes(minute), |session| n = session.get::<i64>("n")).await;
+            assert_eq!(n, Some(1), "gone after {} minutes", minute);
+        }
+
+        let record = store.load(id_of(&cookie)).await.unwrap().unwrap();
+        assert_eq!(record.expires_at, start + minutes(60));
+
+        let mut n = None;
+        request(&sessions, Some(&cookie), start + minutes(60), |session| n = session.get::<i64>("n")).await;
+        assert_eq!(n, None);
+    }
+
+    #[tokio::test]
+    async fn destroy_deletes_the_session_and_the_cookie() {
+        let store = Arc::new(MemorySessionStore::new());
+        let sessions = sessions(&store, 30, 24 * 60);
+        let now = Utc::now();
+
+        let cookie = cookie_pair(&request(&sessions, None, now, |session| session.insert("n", 1).unwrap()).await.unwrap());
+        let removal = request(&sessions, Some(&cookie), now, Session::destroy).await.unwrap();
+
+        assert!(removal.starts_with("session=;"));
+        assert!(removal.contains("Max-Age=0"));
+        assert!(store.load(id_of(&cookie)).await.unwrap().is_none());
+
+        // Nothing to delete for a visitor without a session
+        assert_eq!(request(&sessions, None, now, Session::destroy).await, None);
+    }
+
+    #[tokio::test]
+    async fn signed_cookies_reject_altered_ids() {
+        let store = Arc::new(MemorySessionStore::new());
+        let sessions = sessions(&store, 30, 24 * 60).with_cookie_key(CookieKey::generate());
+        let now = Utc::now();
+
+        let cookie = cookie_pair(&request(&sessions, None, now, |session| session.insert("n", 1).unwrap()).await.unwrap());
+        let (_, id) = id_of(&cookie).split_once('.').unwrap();
+        assert!(store.load(id).await.unwrap().is_some());
+
+        let mut n = None;
+        request(&sessions, Some(&cookie), now, |session| n = session.get::<i64>("n")).await;
+        assert_eq!(n, Some(1));
+
+        // The bare ID, as someone who only learned it from the store would send
+        let mut n = None;
+        request(&sessions, Some(&format!("session={}", id)), now, |session| n = session.get::<i64>("n")).await;
+        assert_eq!(n, None);
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt;
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::error::ApplicationError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApiScope {
+    #[serde(rename = "quotes:read")]
+    ReadQuotes,
+    #[serde(rename = "watchlists:write")]
+    ManageWatchlists,
+    // Minting and revoking keys; implies every other scope
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ApiScope::ReadQuotes => "quotes:read",
+            ApiScope::ManageWatchlists => "watchlists:write",
+            ApiScope::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = ApplicationError;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        match scope {
+            "quotes:read" => Ok(ApiScope::ReadQuotes),
+            "watchlists:write" => Ok(ApiScope::ManageWatchlists),
+            "admin" => Ok(ApiScope::Admin),
+            _ => Err(ApplicationError::OtherError(format!("Unknown API scope: {}", scope))),
+        }
+    }
+}
+
+impl fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// A key as stored; the key itself is only ever seen by whoever minted it
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    // The first characters of the key, so people can tell their keys apart
+    pub prefix: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    pub fn allows(&self, scope: ApiScope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&ApiScope::Admin)
+    }
+}
@@ -1 +1,4 @@
-pub mod symbol;
\ No newline at end of file
+pub mod symbol;
+pub mod session;
+pub mod user;
+pub mod api_key;
\ No newline at end of file
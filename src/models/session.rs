@@ -0,0 +1,17 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+// What a session store keeps for one session ID
+#[derive(Debug, Clone)]
+pub struct SessionRecord {
+    pub data: HashMap<String, Value>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl SessionRecord {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+}
@@ -16,6 +16,8 @@ pub struct Symbol {
 }
 
 impl Symbol {
+    // One argument per column
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: i64,
         symbol: String,
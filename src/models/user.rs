@@ -0,0 +1,12 @@
+use serde::Serialize;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    // A PHC string (algorithm, parameters, salt and hash), never the password
+    #[serde(skip)]
+    pub password_hash: String,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::models::api_key::ApiScope;
+use crate::server::extract::{FromRequest, Json, Path};
+use crate::server::route::Route;
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::services::api_keys::ApiKeys;
+use crate::utils::error::ApplicationError;
+
+const MAX_KEY_NAME_LEN: usize = 100;
+// Ten years; a key meant to outlive that should simply not expire
+const MAX_EXPIRES_IN_DAYS: u32 = 3650;
+
+#[derive(Deserialize)]
+struct MintRequest {
+    name: String,
+    scopes: Vec<ApiScope>,
+    expires_in_days: Option<u32>,
+}
+
+pub struct ApiKeyList {
+    keys: Arc<ApiKeys>,
+}
+
+impl ApiKeyList {
+    pub fn new(keys: Arc<ApiKeys>) -> Self {
+        Self { keys }
+    }
+}
+
+#[async_trait::async_trait]
+impl Route for ApiKeyList {
+    async fn handle(&self, _req: Request) -> Result<Response, ApplicationError> {
+        let keys = self.keys.list().await?;
+        Ok(Response::new(200, "OK").with_json_body(&keys)?)
+    }
+}
+
+pub struct ApiKeyMint {
+    keys: Arc<ApiKeys>,
+}
+
+impl ApiKeyMint {
+    pub fn new(keys: Arc<ApiKeys>) -> Self {
+        Self { keys }
+    }
+}
+
+#[async_trait::async_trait]
+impl Route for ApiKeyMint {
+    async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+        let Json(mint) = Json::<MintRequest>::from_request(&req)?;
+        let name = mint.name.trim();
+
+        if name.is_empty() || name.len() > MAX_KEY_NAME_LEN {
+            return Err(invalid("name must be between 1 and 100 characters"));
+        }
+        if mint.scopes.is_empty() {
+            return Err(invalid("scopes must name at least one scope"));
+        }
+
+        let expires_at = match mint.expires_in_days {
+            Some(days) if days == 0 || days > MAX_EXPIRES_IN_DAYS => {
+                return Err(invalid(&format!("expires_in_days must be between 1 and {}", MAX_EXPIRES_IN_DAYS)));
+            }
+            Some(days) => Some(
+                Utc::now()
+                    .checked_add_signed(Duration::days(days.into()))
+                    .ok_or_else(|| invalid("expires_in_days is too far in the future"))?,
+            ),
+            None => None,
+        };
+        let (key, api_key) = self.keys.mint(name, &mint.scopes, expires_at).await?;
+
+        // The only time the key itself is ever shown
+        Ok(Response::new(201, "Created")
+            .with_header("Location", &format!("/api/v1/admin/keys/{}", api_key.id))
+            .with_json_body(&json!({ "key": key, "api_key": api_key }))?)
+    }
+}
+
+pub struct ApiKeyRevoke {
+    keys: Arc<ApiKeys>,
+}
+
+impl ApiKeyRevoke {
+    pub fn new(keys: Arc<ApiKeys>) -> Self {
+        Self { keys }
+    }
+}
+
+#[async_trait::async_trait]
+impl Route for ApiKeyRevoke {
+    async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+        let Path(id) = Path::<i64>::from_request(&req)?;
+
+        if self.keys.revoke(id).await? {
+            Ok(Response::new(204, "No Content"))
+        } else {
+            Ok(Response::new(404, "Not Found")
+                .with_json_body(&json!({ "error": format!("No active API key with id {}", id) }))?)
+        }
+    }
+}
+
+fn invalid(message: &str) -> ApplicationError {
+    ApplicationError::RequestRejected { status: 422, message: message.to_string() }
+}
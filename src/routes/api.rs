@@ -0,0 +1,76 @@
+use std::sync::Arc;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::server::extract::{FromRequest, Path, Query};
+use crate::server::route::Route;
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::services::database::Database;
+use crate::services::jwt::Claims;
+use crate::utils::error::ApplicationError;
+
+// `?symbol=AAPL&symbol=MSFT` narrows the list down to those tickers
+#[derive(Deserialize)]
+struct SymbolFilter {
+    #[serde(default)]
+    symbol: Vec<String>,
+}
+
+pub struct SymbolList {
+    database: Arc<Database>,
+}
+
+impl SymbolList {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait::async_trait]
+impl Route for SymbolList {
+    async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+        let Query(filter) = Query::<SymbolFilter>::from_request(&req)?;
+
+        let mut symbols = self.database.get_all_symbols().await?;
+        if !filter.symbol.is_empty() {
+            symbols.retain(|symbol| filter.symbol.iter().any(|ticker| ticker.eq_ignore_ascii_case(&symbol.symbol)));
+        }
+
+        Ok(Response::new(200, "OK").with_json_body(&symbols)?)
+    }
+}
+
+pub struct SymbolDetail {
+    database: Arc<Database>,
+}
+
+impl SymbolDetail {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait::async_trait]
+impl Route for SymbolDetail {
+    async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+        let Path(ticker) = Path::<String>::from_request(&req)?;
+
+        match self.database.get_symbol_by_ticker(&ticker).await? {
+            Some(symbol) => Ok(Response::new(200, "OK").with_json_body(&symbol)?),
+            None => Ok(Response::new(404, "Not Found")
+                .with_json_body(&json!({ "error": format!("Symbol '{}' not found", ticker) }))?),
+        }
+    }
+}
+
+// Echoes the caller's verified token claims; mounted behind `JwtAuth`
+pub struct Me;
+
+#[async_trait::async_trait]
+impl Route for Me {
+    async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+        let claims = Claims::from_request(&req)?;
+        Ok(Response::new(200, "OK").with_json_body(&claims)?)
+    }
+}
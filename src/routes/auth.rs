@@ -0,0 +1,283 @@
+use std::sync::Arc;
+use askama::Template;
+use serde::Deserialize;
+
+use crate::middleware::csrf::CsrfToken;
+use crate::middleware::require_login::{CurrentUser, USER_ID_KEY};
+use crate::middleware::session::Session;
+use crate::models::user::User;
+use crate::server::extract::{Form, FromRequest, Query};
+use crate::server::methods::HttpMethod;
+use crate::server::route::Route;
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::services::database::Database;
+use crate::services::password::{hash_password, verify_password};
+use crate::utils::error::ApplicationError;
+
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_PASSWORD_LEN: usize = 128;
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate {
+    username: String,
+    next: String,
+    error: Option<String>,
+    csrf_token: CsrfToken,
+}
+
+#[derive(Template)]
+#[template(path = "register.html")]
+struct RegisterTemplate {
+    username: String,
+    error: Option<String>,
+    csrf_token: CsrfToken,
+}
+
+#[derive(Template)]
+#[template(path = "account.html")]
+struct AccountTemplate {
+    user: User,
+    csrf_token: CsrfToken,
+}
+
+#[derive(Deserialize)]
+struct LoginQuery {
+    #[serde(default)]
+    next: String,
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    username: String,
+    password: String,
+    #[serde(default)]
+    next: String,
+}
+
+#[derive(Deserialize)]
+struct RegisterForm {
+    username: String,
+    password: String,
+    confirm_password: String,
+}
+
+// GET shows the login form, POST checks the credentials
+pub struct Login {
+    database: Arc<Database>,
+}
+
+impl Login {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+
+    async fn submit(&self, req: &Request) -> Result<Response, ApplicationError> {
+        let Form(form) = Form::<LoginForm>::from_request(req)?;
+        let username = form.username.trim();
+
+        let user = self.database.get_user_by_username(username).await?;
+        let password_hash = user.as_ref().map(|user| user.password_hash.clone());
+        let verified = verify_password(form.password, password_hash).await?;
+
+        match user {
+            Some(user) if verified => {
+                let session = Session::from_request(req)?;
+                session.rotate();
+                session.insert(USER_ID_KEY, user.id)?;
+                Ok(Response::redirect(local_redirect(&form.next)))
+            }
+            // Unknown user and wrong password get the same answer
+            _ => {
+                let template = LoginTemplate {
+                    username: username.to_string(),
+                    next: form.next,
+                    error: Some("Invalid username or password".to_string()),
+                    csrf_token: CsrfToken::from_request(req)?,
+                };
+                Ok(Response::new(401, "Unauthorized").with_html_body(&template.render()?))
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Route for Login {
+    async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+        if *req.method() == HttpMethod::POST {
+            return self.submit(&req).await;
+        }
+
+        let Query(query) = Query::<LoginQuery>::from_request(&req)?;
+        let template = LoginTemplate {
+            username: String::new(),
+            next: query.next,
+            error: None,
+            csrf_token: CsrfToken::from_request(&req)?,
+        };
+        Ok(Response::new(200, "OK").with_html_body(&template.render()?))
+    }
+}
+
+// GET shows the registration form, POST creates the account and logs it in
+pub struct Register {
+    database: Arc<Database>,
+}
+
+impl Register {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+
+    async fn submit(&self, req: &Request) -> Result<Response, ApplicationError> {
+        let Form(form) = Form::<RegisterForm>::from_request(req)?;
+        let username = form.username.trim().to_string();
+
+        let rejected = |status: u16, text: &str, error: &str| -> Result<Response, ApplicationError> {
+            let template = RegisterTemplate {
+                username: username.clone(),
+                error: Some(error.to_string()),
+                csrf_token: CsrfToken::from_request(req)?,
+            };
+            Ok(Response::new(status, text).with_html_body(&template.render()?))
+        };
+
+        if let Err(error) = validate_username(&username) {
+            return rejected(422, "Unprocessable Content", error);
+        }
+        if let Err(error) = validate_password(&form.password, &form.confirm_password) {
+            return rejected(422, "Unprocessable Content", error);
+        }
+
+        let password_hash = hash_password(form.password).await?;
+        let Some(user) = self.database.create_user(&username, &password_hash).await? else {
+            return rejected(409, "Conflict", "That username is already taken");
+        };
+
+        let session = Session::from_request(req)?;
+        session.rotate();
+        session.insert(USER_ID_KEY, user.id)?;
+
+        Ok(Response::redirect("/account"))
+    }
+}
+
+#[async_trait::async_trait]
+impl Route for Register {
+    async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+        if *req.method() == HttpMethod::POST {
+            return self.submit(&req).await;
+        }
+
+        let template = RegisterTemplate { username: String::new(), error: None, csrf_token: CsrfToken::from_request(&req)? };
+        Ok(Response::new(200, "OK").with_html_body(&template.render()?))
+    }
+}
+
+// POST only, so a link or an image on another page cannot log anyone out
+pub struct Logout;
+
+#[async_trait::async_trait]
+impl Route for Logout {
+    async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+        Session::from_request(&req)?.destroy();
+        Ok(Response::redirect("/login"))
+    }
+}
+
+// The logged-in user's own page; mounted behind `RequireLogin`
+pub struct Account;
+
+#[async_trait::async_trait]
+impl Route for Account {
+    async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+        let CurrentUser(user) = CurrentUser::from_request(&req)?;
+
+        let template = AccountTemplate { user, csrf_token: CsrfToken::from_request(&req)? };
+        Ok(Response::new(200, "OK").with_html_body(&template.render()?))
+    }
+}
+
+fn validate_username(username: &str) -> Result<(), &'static str> {
+    if !(3..=32).contains(&username.len()) {
+        return Err("Usernames are 3 to 32 characters long");
+    }
+    if !username.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"_-.".contains(&byte)) {
+        return Err("Usernames may only contain letters, digits, '_', '-' and '.'");
+    }
+    Ok(())
+}
+
+fn validate_password(password: &str, confirmation: &str) -> Result<(), &'static str> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err("Passwords need at least 8 characters");
+    }
+    if password.len() > MAX_PASSWORD_LEN {
+        return Err("Passwords may be at most 128 bytes long");
+    }
+    if password != confirmation {
+        return Err("The passwords do not match");
+    }
+    Ok(())
+}
+
+// Only paths on this site are followed after login. "//evil.example" is a
+// protocol-relative URL and would leave it, and a line break would not even
+// make it into the Location header.
+fn local_redirect(next: &str) -> &str {
+    let is_local = next.starts_with('/')
+        && !next.starts_with("//")
+        && !next.starts_with("/\\")
+        && !next.chars().any(char::is_control);
+
+    if is_local { next } else { "/" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_only_local_paths_after_login() {
+        for next in ["/", "/account", "/AAPL?range=1d", "/a//b"] {
+            assert_eq!(local_redirect(next), next);
+        }
+
+        for next in [
+            "",
+            "account",
+            "//evil.com",
+            "/\\evil.com",
+            "https://evil.com",
+            "javascript:alert(1)",
+            "/account\r\nSet-Cookie: x=y",
+            "/tab\tbed",
+        ] {
+            assert_eq!(local_redirect(next), "/", "followed {:?}", next);
+        }
+    }
+
+    #[test]
+    fn usernames_are_short_and_plain() {
+        for username in ["bob", "alice.smith", "a_b-c", &"x".repeat(32)] {
+            assert!(validate_username(username).is_ok(), "refused {:?}", username);
+        }
+        for username in ["", "ab", &"x".repeat(33), "al ice", "alice<script>", "ålice"] {
+            assert!(validate_username(username).is_err(), "accepted {:?}", username);
+        }
+    }
+
+    #[test]
+    fn passwords_are_long_enough_but_bounded() {
+        assert!(validate_password("12345678", "12345678").is_ok());
+        assert!(validate_password(&"p".repeat(MAX_PASSWORD_LEN), &"p".repeat(MAX_PASSWORD_LEN)).is_ok());
+        // Counted in characters at the bottom...
+        assert!(validate_password("ééééééé", "ééééééé").is_err());
+        assert!(validate_password("1234567", "1234567").is_err());
+        // ...and in bytes at the top, which is what hashing costs
+        assert!(validate_password(&"é".repeat(65), &"é".repeat(65)).is_err());
+        assert!(validate_password(&"p".repeat(MAX_PASSWORD_LEN + 1), &"p".repeat(MAX_PASSWORD_LEN + 1)).is_err());
+        assert!(validate_password("12345678", "12345679").is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use crate::server::route::Route;
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::services::database::Database;
+use crate::utils::error::ApplicationError;
+
+const EXPORT_CHANNEL_CAPACITY: usize = 16;
+
+pub struct SymbolExport {
+    database: Arc<Database>,
+}
+
+impl SymbolExport {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait::async_trait]
+impl Route for SymbolExport {
+    async fn handle(&self, _req: Request) -> Result<Response, ApplicationError> {
+        // Loaded before the head goes out: once the 200 is on its way, a
+        // failure could only end the body early and pass for a short export.
+        let symbols = self.database.get_all_symbols().await?;
+
+        let (sender, receiver) = mpsc::channel(EXPORT_CHANNEL_CAPACITY);
+
+        // Rows are written as they are produced, so the response starts
+        // before the whole export has been rendered.
+        tokio::spawn(async move {
+            let header = "symbol,price,change,change_percent,high_price,low_price,open_price,previous_close,last_updated\n";
+            if sender.send(header.as_bytes().to_vec()).await.is_err() {
+                return;
+            }
+
+            for symbol in symbols {
+                let row = format!(
+                    "{},{},{},{},{},{},{},{},{}\n",
+                    symbol.symbol,
+                    symbol.price,
+                    symbol.change,
+                    symbol.change_percent,
+                    symbol.high_price,
+                    symbol.low_price,
+                    symbol.open_price,
+                    symbol.previous_close,
+                    symbol.last_updated.to_rfc3339(),
+                );
+
+                if sender.send(row.into_bytes()).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(200, "OK")
+            .with_header("Content-Disposition", "attachment; filename=\"symbols.csv\"")
+            .with_chunked_body(receiver, "text/csv; charset=utf-8"))
+    }
+}
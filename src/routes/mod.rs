@@ -1,3 +1,201 @@
 pub mod root;
 pub mod static_files;
-pub mod detail;
\ No newline at end of file
+pub mod detail;
+pub mod export;
+pub mod api;
+pub mod auth;
+pub mod admin;
+
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::middleware::access_log::AccessLog;
+use crate::middleware::api_key_auth::ApiKeyAuth;
+use crate::middleware::compression::Compression;
+use crate::middleware::cors::{Cors, CorsConfig};
+use crate::middleware::csrf::Csrf;
+use crate::middleware::jwt_auth::JwtAuth;
+use crate::middleware::rate_limit::{spawn_rate_limit_sweeper, RateLimit, RateLimitConfig, RateLimitKey};
+use crate::middleware::require_login::RequireLogin;
+use crate::middleware::security_headers::SecurityHeaders;
+use crate::middleware::session::{SessionConfig, Sessions};
+use crate::models::api_key::ApiScope;
+use crate::server::config::env_or;
+use crate::server::cookie::CookieKey;
+use crate::server::methods::HttpMethod;
+use crate::server::router::Router;
+use crate::services::api_keys::ApiKeys;
+use crate::services::database::Database;
+use crate::services::jwt::JwtVerifier;
+use crate::services::session_store::{spawn_session_sweeper, MemorySessionStore, SessionStore, SqliteSessionStore};
+use crate::utils::error::ApplicationError;
+use self::admin::{ApiKeyList, ApiKeyMint, ApiKeyRevoke};
+use self::api::{Me, SymbolDetail, SymbolList};
+use self::auth::{Account, Login, Logout, Register};
+use self::detail::Detail;
+use self::export::SymbolExport;
+use self::root::Root;
+use self::static_files::StaticFiles;
+
+const DEFAULT_RATE_LIMIT_SWEEP_INTERVAL: u64 = 60;
+
+pub fn build_router(database: Arc<Database>) -> Result<Router, ApplicationError> {
+    let mut router = Router::new();
+    router.layer(Arc::new(AccessLog));
+    // Outside everything that can still add to or replace the body
+    router.layer(Arc::new(Compression::from_env()));
+    router.layer(Arc::new(SecurityHeaders::from_env()));
+
+    // Ahead of the routes so preflights are answered straight away and
+    // error responses still carry the CORS headers a script needs to read them
+    if let Some(cors_config) = CorsConfig::from_env()? {
+        router.layer(Arc::new(Cors::new(cors_config).with_path_prefix("/api/")));
+    }
+
+    let session_config = SessionConfig::from_env();
+    let session_store = session_store(Arc::clone(&database))?;
+    spawn_session_sweeper(Arc::clone(&session_store), session_config.sweep_interval);
+    // Only the login and account routes below use sessions, so assets, pages
+    // and the API never touch the session store
+    let cookie_key = cookie_key()?;
+    let secure_cookie = session_config.secure_cookie;
+    let sessions = Arc::new(Sessions::new(session_store, session_config).with_cookie_key(cookie_key.clone()));
+
+    // Each group below has its own allowance; see `RateLimit`
+    let page_limit = rate_limit("RATE_LIMIT_PAGES", RateLimitConfig { burst: 30, per_minute: 120 }, RateLimitKey::PeerIp)?;
+    let auth_limit = rate_limit("RATE_LIMIT_AUTH", RateLimitConfig { burst: 5, per_minute: 10 }, RateLimitKey::PeerIp)?;
+    let api_limit = rate_limit("RATE_LIMIT_API", RateLimitConfig { burst: 60, per_minute: 600 }, RateLimitKey::ApiKey)?;
+    let api_ip_limit = rate_limit("RATE_LIMIT_API_IP", RateLimitConfig { burst: 120, per_minute: 1200 }, RateLimitKey::PeerIp)?;
+    let sweep_interval = Duration::from_secs(env_or("RATE_LIMIT_SWEEP_INTERVAL", DEFAULT_RATE_LIMIT_SWEEP_INTERVAL));
+    spawn_rate_limit_sweeper(
+        vec![page_limit.clone(), auth_limit.clone(), api_limit.clone(), api_ip_limit.clone()],
+        sweep_interval,
+    );
+
+    // These pages all query the database
+    let mut pages = router.group().layer(page_limit.clone());
+
+    let root = Arc::new(Root::new(Arc::clone(&database)));
+    pages.add_route(HttpMethod::GET, "/", root.clone())?;
+    pages.add_route(HttpMethod::GET, "/index.html", root)?;
+
+    let detail = Arc::new(Detail::new(Arc::clone(&database)));
+    pages.add_route(HttpMethod::GET, "/{ticker}", detail)?;
+
+    let export = Arc::new(SymbolExport::new(Arc::clone(&database)));
+    pages.add_route(HttpMethod::GET, "/export/symbols.csv", export)?;
+
+    let login = Arc::new(Login::new(Arc::clone(&database)));
+    let register = Arc::new(Register::new(Arc::clone(&database)));
+
+    // Every route a form posts to checks its CSRF token. As group middleware
+    // it only runs once a route has matched, so unknown paths and methods
+    // still get their 404, 405 or 501.
+    let csrf = Arc::new(Csrf::new(cookie_key).with_secure_cookie(secure_cookie));
+
+    // Anyone can load these, so they share the page allowance
+    let mut forms = router
+        .group()
+        .layer(page_limit.clone())
+        .layer(sessions.clone())
+        .layer(csrf.clone());
+    forms.add_route(HttpMethod::GET, "/login", login.clone())?;
+    forms.add_route(HttpMethod::GET, "/register", register.clone())?;
+    forms.add_route(HttpMethod::POST, "/logout", Arc::new(Logout))?;
+
+    // Submitting credentials is limited much more tightly, against guessing
+    let mut credentials = router
+        .group()
+        .layer(auth_limit)
+        .layer(sessions.clone())
+        .layer(csrf.clone());
+    credentials.add_route(HttpMethod::POST, "/login", login)?;
+    credentials.add_route(HttpMethod::POST, "/register", register)?;
+
+    // Pages for logged-in users only
+    let mut account = router
+        .group()
+        .layer(page_limit)
+        .layer(sessions)
+        .layer(csrf)
+        .layer(Arc::new(RequireLogin::new(Arc::clone(&database))));
+    account.add_route(HttpMethod::GET, "/account", Arc::new(Account))?;
+
+    for directory in ["css", "js", "images"] {
+        let static_files = Arc::new(StaticFiles::new(format!("static/{}", directory)));
+        router.add_route(HttpMethod::GET, &format!("/{}/*path", directory), static_files)?;
+    }
+
+    let api_keys = Arc::new(ApiKeys::from_env(Arc::clone(&database)));
+    let jwt_verifier = JwtVerifier::from_env()?.map(Arc::new);
+    router.nest("/api/v1", api_v1(Arc::clone(&database), api_keys, api_limit, api_ip_limit, jwt_verifier)?)?;
+
+    Ok(router)
+}
+
+// Every API route needs a key (see `ApiKeyAuth`), except the SSO routes,
+// which take a JWT and only exist when JWT_ALGORITHM is configured
+fn api_v1(
+    database: Arc<Database>,
+    api_keys: Arc<ApiKeys>,
+    api_limit: Arc<RateLimit>,
+    api_ip_limit: Arc<RateLimit>,
+    jwt_verifier: Option<Arc<JwtVerifier>>,
+) -> Result<Router, ApplicationError> {
+    let mut api = Router::new();
+
+    // Checking a key costs a hash and a database lookup, so every peer is
+    // limited before that, however many keys it tries; the per-key limit
+    // needs the key checked first
+    let mut quotes = api
+        .group()
+        .layer(api_ip_limit.clone())
+        .layer(Arc::new(ApiKeyAuth::new(Arc::clone(&api_keys), ApiScope::ReadQuotes)))
+        .layer(api_limit);
+    quotes.add_route(HttpMethod::GET, "/symbols", Arc::new(SymbolList::new(Arc::clone(&database))))?;
+    quotes.add_route(HttpMethod::GET, "/symbols/{ticker}", Arc::new(SymbolDetail::new(database)))?;
+
+    let mut admin = api
+        .group()
+        .prefix("/admin")
+        .layer(api_ip_limit.clone())
+        .layer(Arc::new(ApiKeyAuth::new(Arc::clone(&api_keys), ApiScope::Admin)));
+    admin.add_route(HttpMethod::GET, "/keys", Arc::new(ApiKeyList::new(Arc::clone(&api_keys))))?;
+    admin.add_route(HttpMethod::POST, "/keys", Arc::new(ApiKeyMint::new(Arc::clone(&api_keys))))?;
+    admin.add_route(HttpMethod::DELETE, "/keys/{id}", Arc::new(ApiKeyRevoke::new(api_keys)))?;
+
+    if let Some(verifier) = jwt_verifier {
+        let mut sso = api.group().layer(api_ip_limit).layer(Arc::new(JwtAuth::new(verifier)));
+        sso.add_route(HttpMethod::GET, "/me", Arc::new(Me))?;
+    }
+
+    Ok(api)
+}
+
+fn rate_limit(prefix: &str, default: RateLimitConfig, key: RateLimitKey) -> Result<Arc<RateLimit>, ApplicationError> {
+    Ok(Arc::new(RateLimit::new(RateLimitConfig::from_env(prefix, default)?, key)))
+}
+
+// COOKIE_SECRET keeps signed and encrypted cookies valid across restarts and
+// between instances; a secret that is too short stops the server from starting
+fn cookie_key() -> Result<CookieKey, ApplicationError> {
+    match CookieKey::from_env()? {
+        Some(key) => Ok(key),
+        None => {
+            warn!("COOKIE_SECRET is not set; signed cookies will not survive a restart");
+            Ok(CookieKey::generate())
+        }
+    }
+}
+
+// SESSION_STORE picks where sessions live: "sqlite" (the default) keeps them
+// across restarts, "memory" is handy during development
+fn session_store(database: Arc<Database>) -> Result<Arc<dyn SessionStore>, ApplicationError> {
+    match env::var("SESSION_STORE").as_deref() {
+        Ok("sqlite") | Err(_) => Ok(Arc::new(SqliteSessionStore::new(database))),
+        Ok("memory") => Ok(Arc::new(MemorySessionStore::new())),
+        Ok(other) => Err(ApplicationError::InvalidEnvVar(format!("SESSION_STORE: unknown store '{}'", other))),
+    }
+}
@@ -4,7 +4,6 @@ use askama::Template;
 use crate::server::route::Route;
 use crate::server::request::Request;
 use crate::server::response::Response;
-use crate::server::methods::HttpMethod;
 use crate::models::symbol::Symbol;
 use crate::services::database::Database;
 use crate::utils::error::ApplicationError;
@@ -31,12 +30,12 @@ impl Route for Root {
     async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
         let symbols = self.database.get_all_symbols().await?;
 
-        if let Some(accept) = req.headers().get("accept") {
-            if accept.contains("application/json") {
-                return Ok(
-                    Response::new(200, "OK")
-                )
-            }
+        if let Some(accept) = req.headers().get("accept")
+            && accept.contains("application/json")
+        {
+            return Ok(
+                Response::new(200, "OK")
+            )
         }
 
         let template = SymbolTemplate { symbols };
@@ -48,12 +47,4 @@ impl Route for Root {
         )
 
     }
-
-    fn path_matches(&self, path: &str) -> bool {
-        path == "/" || path == "/index.html"
-    }
-
-    fn method_matches(&self, method: &HttpMethod) -> bool {
-        method == &HttpMethod::GET
-    }
 }
\ No newline at end of file
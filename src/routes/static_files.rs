@@ -1,13 +1,17 @@
 use std::fs;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 use async_trait::async_trait;
+use tokio::fs::File;
 
+use crate::server::compression::{negotiate, ContentEncoding};
 use crate::server::route::Route;
 use crate::server::request::Request;
-use crate::server::response::Response;
-use crate::server::methods::HttpMethod;
+use crate::server::response::{BodyStream, Response};
 use crate::utils::error::ApplicationError;
 
+// Serves files below `base_path`, mounted on a pattern ending in `*path`. A
+// "styles.css.br" or "styles.css.gz" next to a file is sent instead of it to
+// clients that accept that encoding.
 pub struct StaticFiles {
     base_path: String,
 }
@@ -32,36 +36,123 @@ impl StaticFiles {
             "application/octet-stream"
         }
     }
+
+    // The precompressed copies of `file_path` that exist, in order of preference
+    fn precompressed_variants(&self, base: &Path, file_path: &Path) -> Vec<(ContentEncoding, PathBuf)> {
+        ContentEncoding::ALL
+            .into_iter()
+            .filter_map(|encoding| {
+                let mut variant = file_path.as_os_str().to_owned();
+                variant.push(".");
+                variant.push(encoding.file_extension()?);
+
+                contained_file(base, Path::new(&variant)).map(|variant| (encoding, variant))
+            })
+            .collect()
+    }
+}
+
+// The file `path` names below `base`, which must be canonical. Anything that
+// could lead outside of it is refused: absolute paths, "..", and symlinks
+// that point elsewhere.
+fn resolve(base: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path);
+    if path.is_empty() || !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        return None;
+    }
+
+    contained_file(base, &base.join(relative))
+}
+
+fn contained_file(base: &Path, path: &Path) -> Option<PathBuf> {
+    let path = fs::canonicalize(path).ok()?;
+    (path.starts_with(base) && path.is_file()).then_some(path)
 }
 
 #[async_trait]
 impl Route for StaticFiles {
     async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
-        let path = req.path().trim_start_matches('/');
-        let file_path = Path::new(&self.base_path).join(path);
-
-        if !file_path.exists() || !file_path.is_file() {
+        let path = req.param("path").unwrap_or_default();
+        let Some((base, file_path)) = fs::canonicalize(&self.base_path)
+            .ok()
+            .and_then(|base| resolve(&base, path).map(|file_path| (base, file_path)))
+        else {
             return Ok(Response::new(404, "Not Found"));
-        }
+        };
+
+        let variants = self.precompressed_variants(&base, &file_path);
+        let available: Vec<ContentEncoding> = variants.iter().map(|(encoding, _)| *encoding).collect();
+        let accept_encoding = req.headers().get_combined("accept-encoding");
+        let precompressed = negotiate(accept_encoding.as_deref(), &available)
+            .and_then(|chosen| variants.into_iter().find(|(encoding, _)| *encoding == chosen));
 
-        let content = match fs::read(&file_path) {
-            Ok(content) => content,
+        let (file_path, content_encoding) = match precompressed {
+            Some((encoding, variant)) => (variant, Some(encoding)),
+            None => (file_path, None),
+        };
+
+        // Files are streamed from disk rather than read into memory first
+        let file = match File::open(&file_path).await {
+            Ok(file) => file,
             Err(_) => return Ok(Response::new(500, "Internal Server Error")),
         };
+        let length = file.metadata().await?.len();
 
         let content_type = self.get_content_type(path);
 
-        Ok(Response::new(200, "OK")
-            .with_raw_body(content, content_type))
+        let mut response = Response::new(200, "OK").with_stream_body(BodyStream::from_reader(file, Some(length)), content_type);
+
+        // The response depends on Accept-Encoding whenever there was a choice
+        if !available.is_empty() {
+            response = response.with_appended_header("Vary", "Accept-Encoding");
+        }
+        if let Some(encoding) = content_encoding {
+            response = response.with_header("Content-Encoding", encoding.as_str());
+        }
+
+        Ok(response)
     }
+}
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::server::headers::HeaderMap;
+    use crate::server::methods::HttpMethod;
+    use crate::server::request::QueryParams;
+    use crate::server::version::HttpVersion;
+
+    async fn get(static_files: &StaticFiles, path: &str) -> u16 {
+        let params = HashMap::from([("path".to_string(), path.to_string())]);
+        let req = Request::new(HttpMethod::GET, format!("/css/{}", path), HttpVersion::Http11, HeaderMap::new(), QueryParams::new(Vec::new()), Vec::new())
+            .with_params(params);
 
-    fn path_matches(&self, path: &str) -> bool {
-        path.starts_with("/css/") ||
-            path.starts_with("/js/") ||
-            path.starts_with("/images/")
+        static_files.handle(req).await.unwrap().status_code()
     }
 
-    fn method_matches(&self, method: &HttpMethod) -> bool {
-        method == &HttpMethod::GET
+    #[tokio::test]
+    async fn refuses_paths_outside_the_base_directory() {
+        let root = std::env::temp_dir().join(format!("static-files-test-{}", std::process::id()));
+        let base = root.join("css");
+        fs::create_dir_all(&base).unwrap();
+        fs::write(base.join("styles.css"), "body {}").unwrap();
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+
+        let static_files = StaticFiles::new(base.to_string_lossy().into_owned());
+
+        assert_eq!(get(&static_files, "styles.css").await, 200);
+        assert_eq!(get(&static_files, "../secret.txt").await, 404);
+        assert_eq!(get(&static_files, &root.join("secret.txt").to_string_lossy()).await, 404);
+        assert_eq!(get(&static_files, "/etc/hostname").await, 404);
+        assert_eq!(get(&static_files, "./styles.css").await, 404);
+        assert_eq!(get(&static_files, "").await, 404);
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("secret.txt"), base.join("link.css")).unwrap();
+            assert_eq!(get(&static_files, "link.css").await, 404);
+        }
+
+        fs::remove_dir_all(&root).unwrap();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,234 @@
+use std::io::{self, Write};
+use brotli::CompressorWriter;
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+// Brotli at its top quality is far too slow to run per request; 5 is close to
+// gzip in speed and still noticeably smaller
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW_BITS: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl ContentEncoding {
+    // In the order the server prefers them when the client has no preference
+    pub const ALL: [ContentEncoding; 3] = [ContentEncoding::Brotli, ContentEncoding::Gzip, ContentEncoding::Deflate];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
+
+    // What a precompressed copy of a static file is named after, if the
+    // encoding has such a convention
+    pub fn file_extension(&self) -> Option<&'static str> {
+        match self {
+            ContentEncoding::Brotli => Some("br"),
+            ContentEncoding::Gzip => Some("gz"),
+            ContentEncoding::Deflate => None,
+        }
+    }
+
+    fn matches(&self, coding: &str) -> bool {
+        coding.eq_ignore_ascii_case(self.as_str()) || (*self == ContentEncoding::Gzip && coding.eq_ignore_ascii_case("x-gzip"))
+    }
+}
+
+// Picks the encoding the client weighs highest in its Accept-Encoding header,
+// out of `available`, which is in the server's order of preference for ties.
+// `None` means the body is best sent as it is: the client did not ask for
+// compression, refused every available encoding, or prefers identity.
+pub fn negotiate(accept_encoding: Option<&str>, available: &[ContentEncoding]) -> Option<ContentEncoding> {
+    let preferences = parse_accept_encoding(accept_encoding?);
+    let weight = |matches: &dyn Fn(&str) -> bool| {
+        preferences
+            .iter()
+            .find(|(coding, _)| matches(coding))
+            .or_else(|| preferences.iter().find(|(coding, _)| coding == "*"))
+            .map(|(_, q)| *q)
+    };
+
+    let mut best: Option<(ContentEncoding, f32)> = None;
+    for encoding in available {
+        let q = weight(&|coding| encoding.matches(coding)).unwrap_or(0.0);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((*encoding, q));
+        }
+    }
+
+    // Identity is only preferred when the client says so explicitly
+    let identity = preferences.iter().find(|(coding, _)| coding.eq_ignore_ascii_case("identity")).map(|(_, q)| *q);
+    match (best, identity) {
+        (Some((_, q)), Some(identity_q)) if identity_q > q => None,
+        (best, _) => best.map(|(encoding, _)| encoding),
+    }
+}
+
+// "gzip;q=0.8, br" into its codings and their weights. Entries with an
+// unreadable weight are left out rather than guessed at.
+fn parse_accept_encoding(header: &str) -> Vec<(String, f32)> {
+    header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let coding = parts.next().filter(|coding| !coding.is_empty())?;
+
+            let mut q = 1.0;
+            for parameter in parts {
+                if let Some((name, value)) = parameter.split_once('=')
+                    && name.trim().eq_ignore_ascii_case("q")
+                {
+                    q = value.trim().parse::<f32>().ok().filter(|q| (0.0..=1.0).contains(q))?;
+                }
+            }
+
+            Some((coding.to_ascii_lowercase(), q))
+        })
+        .collect()
+}
+
+pub fn encode(encoding: ContentEncoding, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(encoding);
+    let mut encoded = encoder.write(data)?;
+    encoded.extend(encoder.finish()?);
+    Ok(encoded)
+}
+
+// Compresses a body that arrives in pieces. Output is handed back as the
+// compressor produces it, which need not be after every piece.
+pub enum Encoder {
+    Brotli(Box<CompressorWriter<Vec<u8>>>),
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    pub fn new(encoding: ContentEncoding) -> Self {
+        match encoding {
+            ContentEncoding::Brotli => Encoder::Brotli(Box::new(CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW_BITS,
+            ))),
+            ContentEncoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::default())),
+            // "deflate" in HTTP is the zlib format, not a raw deflate stream
+            ContentEncoding::Deflate => Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::default())),
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let output = match self {
+            Encoder::Brotli(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+            Encoder::Deflate(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+        };
+
+        Ok(std::mem::take(output))
+    }
+
+    // Whatever is still buffered, plus the end of the compressed stream
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use super::*;
+
+    use ContentEncoding::{Brotli, Deflate, Gzip};
+
+    #[test]
+    fn picks_the_highest_weighted_available_encoding() {
+        assert_eq!(negotiate(Some("gzip, deflate, br"), &ContentEncoding::ALL), Some(Brotli));
+        assert_eq!(negotiate(Some("br;q=0.5, gzip"), &ContentEncoding::ALL), Some(Gzip));
+        assert_eq!(negotiate(Some("GZIP;Q=0.9, deflate;q=0.95"), &ContentEncoding::ALL), Some(Deflate));
+        assert_eq!(negotiate(Some("x-gzip"), &ContentEncoding::ALL), Some(Gzip));
+        assert_eq!(negotiate(Some("br"), &[Gzip, Deflate]), None);
+    }
+
+    #[test]
+    fn falls_back_to_the_wildcard() {
+        assert_eq!(negotiate(Some("*"), &ContentEncoding::ALL), Some(Brotli));
+        assert_eq!(negotiate(Some("br;q=0, *"), &ContentEncoding::ALL), Some(Gzip));
+        assert_eq!(negotiate(Some("gzip;q=0.5, *;q=0"), &ContentEncoding::ALL), Some(Gzip));
+        assert_eq!(negotiate(Some("*;q=0"), &ContentEncoding::ALL), None);
+    }
+
+    #[test]
+    fn leaves_the_body_alone_when_identity_wins_or_nothing_is_asked() {
+        assert_eq!(negotiate(None, &ContentEncoding::ALL), None);
+        assert_eq!(negotiate(Some(""), &ContentEncoding::ALL), None);
+        assert_eq!(negotiate(Some("identity"), &ContentEncoding::ALL), None);
+        assert_eq!(negotiate(Some("gzip;q=0.5, identity"), &ContentEncoding::ALL), None);
+        assert_eq!(negotiate(Some("gzip, identity;q=0.5"), &ContentEncoding::ALL), Some(Gzip));
+    }
+
+    #[test]
+    fn drops_entries_with_unreadable_weights() {
+        assert_eq!(negotiate(Some("br;q=high, gzip;q=0.1"), &ContentEncoding::ALL), Some(Gzip));
+        assert_eq!(negotiate(Some("br;q=2, gzip;q=-1"), &ContentEncoding::ALL), None);
+    }
+
+    fn decode(encoding: ContentEncoding, data: &[u8]) -> Vec<u8> {
+        let mut decoded = Vec::new();
+        match encoding {
+            Brotli => brotli::Decompressor::new(data, BROTLI_BUFFER_SIZE).read_to_end(&mut decoded),
+            Gzip => flate2::read::GzDecoder::new(data).read_to_end(&mut decoded),
+            Deflate => flate2::read::ZlibDecoder::new(data).read_to_end(&mut decoded),
+        }
+        .unwrap();
+        decoded
+    }
+
+    #[test]
+    fn encoded_bodies_round_trip() {
+        let body = "<p>The quick brown fox jumps over the lazy dog.</p>\n".repeat(200);
+
+        for encoding in ContentEncoding::ALL {
+            let encoded = encode(encoding, body.as_bytes()).unwrap();
+            assert!(encoded.len() < body.len(), "{} did not shrink the body", encoding.as_str());
+            assert_eq!(decode(encoding, &encoded), body.as_bytes());
+        }
+    }
+
+    #[test]
+    fn streamed_pieces_decode_to_the_whole_body() {
+        let body = "0123456789abcdef".repeat(1000);
+
+        for encoding in ContentEncoding::ALL {
+            let mut encoder = Encoder::new(encoding);
+            let mut encoded = Vec::new();
+            for piece in body.as_bytes().chunks(777) {
+                encoded.extend(encoder.write(piece).unwrap());
+            }
+            encoded.extend(encoder.finish().unwrap());
+
+            assert_eq!(decode(encoding, &encoded), body.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_KEEP_ALIVE_TIMEOUT: u64 = 5;
+const DEFAULT_READ_TIMEOUT: u64 = 30;
+const DEFAULT_MAX_HEADER_SIZE: usize = 16 * 1024;
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+const DEFAULT_H2_MAX_CONCURRENT_STREAMS: u32 = 100;
+const DEFAULT_H2_STREAM_WINDOW_SIZE: u32 = 256 * 1024;
+const DEFAULT_H2_CONNECTION_WINDOW_SIZE: u32 = 1024 * 1024;
+const DEFAULT_H2_MAX_FRAME_SIZE: u32 = 16 * 1024;
+// The limits RFC 9113 puts on these settings
+const H2_FRAME_SIZE_RANGE: std::ops::RangeInclusive<u32> = 16_384..=16_777_215;
+const H2_MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub keep_alive_timeout: Duration,
+    pub read_timeout: Duration,
+    pub max_header_size: usize,
+    pub max_body_size: usize,
+    pub http2: Http2Config,
+}
+
+#[derive(Debug, Clone)]
+pub struct Http2Config {
+    // Offered over ALPN and accepted as prior-knowledge h2c when on
+    pub enabled: bool,
+    // Requests a client may have in flight at once on one connection
+    pub max_concurrent_streams: u32,
+    // How much request body a client may send before the server reads it,
+    // per stream and across the whole connection
+    pub initial_stream_window_size: u32,
+    pub initial_connection_window_size: u32,
+    pub max_frame_size: u32,
+}
+
+impl Http2Config {
+    pub fn from_env() -> Self {
+        let config = Self {
+            enabled: env_or("HTTP2_ENABLED", true),
+            max_concurrent_streams: env_or("HTTP2_MAX_CONCURRENT_STREAMS", DEFAULT_H2_MAX_CONCURRENT_STREAMS),
+            initial_stream_window_size: env_or("HTTP2_STREAM_WINDOW_SIZE", DEFAULT_H2_STREAM_WINDOW_SIZE),
+            initial_connection_window_size: env_or("HTTP2_CONNECTION_WINDOW_SIZE", DEFAULT_H2_CONNECTION_WINDOW_SIZE),
+            max_frame_size: env_or("HTTP2_MAX_FRAME_SIZE", DEFAULT_H2_MAX_FRAME_SIZE),
+        };
+
+        // Out-of-range values would only surface as a panic in the h2 crate
+        // on the first connection
+        if !H2_FRAME_SIZE_RANGE.contains(&config.max_frame_size) {
+            panic!("HTTP2_MAX_FRAME_SIZE must be between 16384 and 16777215");
+        }
+        if config.initial_stream_window_size > H2_MAX_WINDOW_SIZE || config.initial_connection_window_size > H2_MAX_WINDOW_SIZE {
+            panic!("HTTP/2 window sizes must be at most 2^31 - 1");
+        }
+
+        config
+    }
+}
+
+impl Default for Http2Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_concurrent_streams: DEFAULT_H2_MAX_CONCURRENT_STREAMS,
+            initial_stream_window_size: DEFAULT_H2_STREAM_WINDOW_SIZE,
+            initial_connection_window_size: DEFAULT_H2_CONNECTION_WINDOW_SIZE,
+            max_frame_size: DEFAULT_H2_MAX_FRAME_SIZE,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn from_env() -> Self {
+        Self {
+            keep_alive_timeout: Duration::from_secs(env_or("KEEP_ALIVE_TIMEOUT", DEFAULT_KEEP_ALIVE_TIMEOUT)),
+            read_timeout: Duration::from_secs(env_or("READ_TIMEOUT", DEFAULT_READ_TIMEOUT)),
+            max_header_size: env_or("MAX_HEADER_SIZE", DEFAULT_MAX_HEADER_SIZE),
+            max_body_size: env_or("MAX_BODY_SIZE", DEFAULT_MAX_BODY_SIZE),
+            http2: Http2Config::from_env(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            keep_alive_timeout: Duration::from_secs(DEFAULT_KEEP_ALIVE_TIMEOUT),
+            read_timeout: Duration::from_secs(DEFAULT_READ_TIMEOUT),
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            http2: Http2Config::default(),
+        }
+    }
+}
+
+// Optional settings fall back to their default when unset, but a malformed
+// value is a configuration mistake and should stop the server from starting.
+pub(crate) fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value
+            .parse::<T>()
+            .unwrap_or_else(|_| panic!("{} must be a valid value", name)),
+        Err(_) => default,
+    }
+}
@@ -0,0 +1,387 @@
+use std::env;
+use std::fmt;
+use std::time::Duration;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::server::headers::HeaderMap;
+use crate::server::methods::is_token;
+use crate::utils::error::ApplicationError;
+
+const MIN_SECRET_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+// A cookie to send with `Response::with_cookie`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<DateTime<Utc>>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    // Overwrites the cookie called `name` with one that has already expired,
+    // which is how a server asks the browser to delete it. Path and Domain
+    // have to match the ones the cookie was set with.
+    pub fn removal(name: &str) -> Self {
+        Self::new(name, "")
+            .with_max_age(Duration::ZERO)
+            .with_expires(DateTime::UNIX_EPOCH)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn with_domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_expires(mut self, expires: DateTime<Utc>) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn with_http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    // The Set-Cookie header value, once the parts that end up on the wire
+    // are known not to break out of the header or of their attribute.
+    pub fn to_header_value(&self) -> Result<String, ApplicationError> {
+        if !is_token(&self.name) {
+            return Err(ApplicationError::InvalidCookie(format!("'{}' is not a valid cookie name", self.name)));
+        }
+        if !self.value.bytes().all(is_cookie_octet) {
+            return Err(ApplicationError::InvalidCookie(format!("value of '{}' needs encoding", self.name)));
+        }
+
+        let attributes = [&self.path, &self.domain];
+        if attributes.iter().flat_map(|value| value.as_deref()).any(|value| value.contains([';', '\r', '\n'])) {
+            return Err(ApplicationError::InvalidCookie(format!("attributes of '{}' contain a separator", self.name)));
+        }
+
+        Ok(self.to_string())
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", expires.format("%a, %d %b %Y %H:%M:%S GMT"))?;
+        }
+        // Browsers drop SameSite=None cookies that are not also Secure
+        if self.secure || self.same_site == Some(SameSite::None) {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+
+        Ok(())
+    }
+}
+
+// RFC 6265 cookie-octet: printable ASCII minus whitespace, '"', ',', ';' and '\'
+fn is_cookie_octet(byte: u8) -> bool {
+    matches!(byte, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+// The cookies a request came with, from all of its Cookie headers
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Vec<(String, String)>,
+}
+
+impl CookieJar {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let cookies = headers
+            .get_all("cookie")
+            .flat_map(|header| header.split(';'))
+            .filter_map(|pair| {
+                // Pairs without '=' are not cookies, and browsers send nothing
+                // else we could act on, so they are skipped rather than rejected
+                let (name, value) = pair.trim().split_once('=')?;
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                Some((name.trim().to_string(), value.to_string()))
+            })
+            .filter(|(name, _)| !name.is_empty())
+            .collect();
+
+        Self { cookies }
+    }
+
+    // A browser sends the most specific path first when names collide
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The value of a cookie set through `CookieKey::sign`, if it is intact
+    pub fn get_signed(&self, key: &CookieKey, name: &str) -> Option<String> {
+        key.verify(name, self.get(name)?)
+    }
+
+    // The plaintext of a cookie set through `CookieKey::encrypt`
+    pub fn get_private(&self, key: &CookieKey, name: &str) -> Option<String> {
+        key.decrypt(name, self.get(name)?)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cookies.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+}
+
+// Protects cookie values against tampering (signed) or against being read at
+// all (encrypted). Both keys are derived from one secret so a single setting
+// covers every cookie; the cookie name is bound into the MAC and the
+// ciphertext so a value cannot be replayed under another name.
+#[derive(Clone)]
+pub struct CookieKey {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+impl CookieKey {
+    pub fn from_secret(secret: &[u8]) -> Result<Self, ApplicationError> {
+        if secret.len() < MIN_SECRET_LEN {
+            return Err(ApplicationError::InvalidCookie(format!(
+                "the cookie secret must be at least {} bytes long",
+                MIN_SECRET_LEN
+            )));
+        }
+
+        let hkdf = Hkdf::<Sha256>::new(None, secret);
+        let mut signing = [0; 32];
+        let mut encryption = [0; 32];
+        hkdf.expand(b"cookie signing", &mut signing).expect("32 bytes is a valid HKDF length");
+        hkdf.expand(b"cookie encryption", &mut encryption).expect("32 bytes is a valid HKDF length");
+
+        Ok(Self { signing, encryption })
+    }
+
+    // A random key that lasts as long as the process, for when no secret is
+    // configured. Cookies protected with it are worthless after a restart.
+    pub fn generate() -> Self {
+        let mut secret = [0; MIN_SECRET_LEN];
+        OsRng.fill_bytes(&mut secret);
+        Self::from_secret(&secret).expect("a generated secret has the minimum length")
+    }
+
+    // `None` when COOKIE_SECRET is unset
+    pub fn from_env() -> Result<Option<Self>, ApplicationError> {
+        match env::var("COOKIE_SECRET") {
+            Ok(secret) => Self::from_secret(secret.as_bytes())
+                .map(Some)
+                .map_err(|e| ApplicationError::InvalidEnvVar(format!("COOKIE_SECRET: {}", e))),
+            Err(_) => Ok(None),
+        }
+    }
+
+    // The value stays readable but any change to it is detected
+    pub fn sign(&self, mut cookie: Cookie) -> Cookie {
+        let tag = self.mac(&cookie.name, &cookie.value).finalize().into_bytes();
+        cookie.value = format!("{}.{}", URL_SAFE_NO_PAD.encode(tag), cookie.value);
+        cookie
+    }
+
+    pub fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let (tag, value) = signed.split_once('.')?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+
+        // verify_slice compares in constant time
+        self.mac(name, value).verify_slice(&tag).ok()?;
+        Some(value.to_string())
+    }
+
+    pub fn encrypt(&self, mut cookie: Cookie) -> Cookie {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.encryption));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload { msg: cookie.value.as_bytes(), aad: cookie.name.as_bytes() };
+        let ciphertext = cipher.encrypt(&nonce, payload).expect("AES-GCM encryption of a cookie cannot fail");
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        cookie.value = URL_SAFE_NO_PAD.encode(sealed);
+        cookie
+    }
+
+    pub fn decrypt(&self, name: &str, sealed: &str) -> Option<String> {
+        let sealed = URL_SAFE_NO_PAD.decode(sealed).ok()?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.encryption));
+        let payload = Payload { msg: ciphertext, aad: name.as_bytes() };
+        let plaintext = cipher.decrypt(Nonce::from_slice(nonce), payload).ok()?;
+
+        String::from_utf8(plaintext).ok()
+    }
+
+    fn mac(&self, name: &str, value: &str) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.signing).expect("HMAC accepts keys of any length");
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+}
+
+// Never print the keys themselves
+impl fmt::Debug for CookieKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CookieKey").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_from(secret: &str) -> CookieKey {
+        CookieKey::from_secret(secret.repeat(32).as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn rejects_short_secrets() {
+        assert!(CookieKey::from_secret(&[7; MIN_SECRET_LEN - 1]).is_err());
+        assert!(CookieKey::from_secret(&[7; MIN_SECRET_LEN]).is_ok());
+    }
+
+    #[test]
+    fn generated_keys_differ() {
+        let first = CookieKey::generate();
+        let signed = first.sign(Cookie::new("session", "id")).value().to_string();
+
+        assert_eq!(first.verify("session", &signed), Some("id".to_string()));
+        assert_eq!(CookieKey::generate().verify("session", &signed), None);
+    }
+
+    #[test]
+    fn signed_value_verifies_only_unchanged() {
+        let key = key_from("a");
+        let signed = key.sign(Cookie::new("theme", "dark")).value().to_string();
+
+        assert!(signed.ends_with(".dark"));
+        assert_eq!(key.verify("theme", &signed), Some("dark".to_string()));
+
+        let tampered = signed.replace(".dark", ".light");
+        assert_eq!(key.verify("theme", &tampered), None);
+        assert_eq!(key.verify("other", &signed), None);
+        assert_eq!(key_from("b").verify("theme", &signed), None);
+        assert_eq!(key.verify("theme", "dark"), None);
+    }
+
+    #[test]
+    fn encrypted_value_round_trips_and_hides_the_value() {
+        let key = key_from("a");
+        let first = key.encrypt(Cookie::new("session", "user=42")).value().to_string();
+        let second = key.encrypt(Cookie::new("session", "user=42")).value().to_string();
+
+        assert!(!first.contains("user"));
+        // A fresh nonce every time
+        assert_ne!(first, second);
+        assert_eq!(key.decrypt("session", &first), Some("user=42".to_string()));
+
+        assert_eq!(key.decrypt("other", &first), None);
+        assert_eq!(key_from("b").decrypt("session", &first), None);
+        assert_eq!(key.decrypt("session", &first[..first.len() - 2]), None);
+        assert_eq!(key.decrypt("session", "short"), None);
+    }
+}
@@ -0,0 +1,486 @@
+use serde::de::value::{Error as DeError, MapDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, Deserializer, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+use crate::server::request::Request;
+use crate::server::urlencoded::parse_urlencoded;
+use crate::utils::error::ApplicationError;
+
+// Typed access to parts of a request. Failures come back as
+// `ApplicationError::RequestRejected`, which the router turns into a 4xx
+// response, so handlers can simply use `?`:
+//
+//     let Path(ticker) = Path::<String>::from_request(&req)?;
+pub trait FromRequest: Sized {
+    fn from_request(req: &Request) -> Result<Self, ApplicationError>;
+}
+
+pub struct Query<T>(pub T);
+
+pub struct Path<T>(pub T);
+
+pub struct Json<T>(pub T);
+
+pub struct Form<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(req: &Request) -> Result<Self, ApplicationError> {
+        let pairs = req.query_params().iter().map(|(key, value)| (key.to_string(), value.to_string()));
+
+        T::deserialize(PairsDeserializer::new(pairs))
+            .map(Query)
+            .map_err(|e| rejected(400, format!("Invalid query string: {}", e)))
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+    fn from_request(req: &Request) -> Result<Self, ApplicationError> {
+        let pairs = req.params().iter().map(|(key, value)| (key.clone(), value.clone()));
+
+        T::deserialize(PairsDeserializer::new(pairs))
+            .map(Path)
+            .map_err(|e| rejected(400, format!("Invalid path parameters: {}", e)))
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(req: &Request) -> Result<Self, ApplicationError> {
+        let is_json = media_type(req).is_some_and(|media_type| {
+            media_type == "application/json" || media_type.ends_with("+json")
+        });
+        if !is_json {
+            return Err(rejected(415, "Expected an application/json body".to_string()));
+        }
+
+        // Well-formed JSON of the wrong shape is a semantic problem (422),
+        // anything that does not even parse is a malformed request (400).
+        serde_json::from_slice(req.body()).map(Json).map_err(|e| match e.classify() {
+            serde_json::error::Category::Data => rejected(422, format!("Invalid JSON body: {}", e)),
+            _ => rejected(400, format!("Malformed JSON body: {}", e)),
+        })
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Form<T> {
+    fn from_request(req: &Request) -> Result<Self, ApplicationError> {
+        if media_type(req).as_deref() != Some("application/x-www-form-urlencoded") {
+            return Err(rejected(415, "Expected an application/x-www-form-urlencoded body".to_string()));
+        }
+
+        let body = std::str::from_utf8(req.body())
+            .map_err(|_| rejected(400, "Form body is not valid UTF-8".to_string()))?;
+        let pairs = parse_urlencoded(body)
+            .map_err(|e| rejected(400, format!("Malformed form body: {}", e)))?;
+
+        T::deserialize(PairsDeserializer::new(pairs))
+            .map(Form)
+            .map_err(|e| rejected(422, format!("Invalid form body: {}", e)))
+    }
+}
+
+fn rejected(status: u16, message: String) -> ApplicationError {
+    ApplicationError::RequestRejected { status, message }
+}
+
+// "Application/JSON; charset=utf-8" -> "application/json"
+fn media_type(req: &Request) -> Option<String> {
+    req.headers()
+        .get("content-type")
+        .and_then(|value| value.split(';').next())
+        .map(|media_type| media_type.trim().to_lowercase())
+}
+
+// Deserializes key/value pairs (query strings, path parameters, form bodies)
+// into a struct. Repeated keys feed sequence fields, numbers and booleans
+// are parsed from their text, and a type with no fields of its own, such as
+// `Path<String>`, reads the value of the only pair.
+struct PairsDeserializer {
+    entries: Vec<(String, Vec<String>)>,
+}
+
+impl PairsDeserializer {
+    fn new(pairs: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut entries: Vec<(String, Vec<String>)> = Vec::new();
+
+        for (key, value) in pairs {
+            match entries.iter_mut().find(|(existing, _)| *existing == key) {
+                Some((_, values)) => values.push(value),
+                None => entries.push((key, vec![value])),
+            }
+        }
+
+        Self { entries }
+    }
+
+    fn single(self) -> Result<ValuesDeserializer, DeError> {
+        let mut entries = self.entries.into_iter();
+        match (entries.next(), entries.next()) {
+            (Some((_, values)), None) => Ok(ValuesDeserializer(values)),
+            _ => Err(de::Error::custom("expected exactly one value")),
+        }
+    }
+}
+
+macro_rules! forward_to_single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for PairsDeserializer {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let entries = self.entries.into_iter().map(|(key, values)| (key, ValuesDeserializer(values)));
+        visitor.visit_map(MapDeserializer::new(entries))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_option
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 bytes byte_buf unit unit_struct seq tuple tuple_struct identifier ignored_any
+    }
+}
+
+// All values given for one key
+struct ValuesDeserializer(Vec<String>);
+
+impl ValuesDeserializer {
+    // A scalar field takes the last of repeated values
+    fn last(mut self) -> PartDeserializer {
+        PartDeserializer(self.0.pop().unwrap_or_default())
+    }
+}
+
+impl<'de> IntoDeserializer<'de, DeError> for ValuesDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+macro_rules! forward_to_last {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.last().$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for ValuesDeserializer {
+    type Error = DeError;
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_seq(SeqDeserializer::new(self.0.into_iter().map(PartDeserializer)))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.last().deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.last().deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        self.last().deserialize_unit_struct(name, visitor)
+    }
+
+    forward_to_last! {
+        deserialize_any deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32
+        deserialize_i64 deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32
+        deserialize_u64 deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char
+        deserialize_str deserialize_string deserialize_bytes deserialize_byte_buf
+        deserialize_option deserialize_unit deserialize_map deserialize_identifier
+        deserialize_ignored_any
+    }
+}
+
+// A single textual value
+struct PartDeserializer(String);
+
+impl<'de> IntoDeserializer<'de, DeError> for PartDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match self.0.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => Err(de::Error::invalid_value(de::Unexpected::Str(&self.0), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for PartDeserializer {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_string(self.0)
+    }
+
+    // `?limit=` means no limit rather than an unparsable one
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    parse_value! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use serde::Deserialize;
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Interval {
+        Daily,
+        Weekly,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Search {
+        symbol: Vec<String>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+        ratio: f64,
+        adjusted: bool,
+        interval: Interval,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Login {
+        username: String,
+        remember: Option<bool>,
+    }
+
+    fn request(head: &str, body: &str) -> Request {
+        let raw = format!("{}\r\nContent-Length: {}\r\n\r\n{}", head, body.len(), body);
+        Request::try_from(raw.as_bytes()).unwrap()
+    }
+
+    fn form(content_type: &str, body: &str) -> Request {
+        request(&format!("POST /login HTTP/1.1\r\nContent-Type: {}", content_type), body)
+    }
+
+    fn status<T>(result: Result<T, ApplicationError>) -> u16 {
+        match result {
+            Err(ApplicationError::RequestRejected { status, .. }) => status,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("expected a rejection"),
+        }
+    }
+
+    #[test]
+    fn reads_queries_into_structs() {
+        let req = request(
+            "GET /quotes?symbol=AAPL&symbol=MSFT&limit=&ratio=0.5&adjusted=true&interval=weekly HTTP/1.1",
+            "",
+        );
+
+        let Query(search) = Query::<Search>::from_request(&req).unwrap();
+        assert_eq!(
+            search,
+            Search {
+                symbol: vec!["AAPL".to_string(), "MSFT".to_string()],
+                limit: None,
+                offset: None,
+                ratio: 0.5,
+                adjusted: true,
+                interval: Interval::Weekly,
+            }
+        );
+
+        let req = request("GET /quotes?symbol=AAPL&limit=5&limit=10&ratio=1&adjusted=false&interval=daily HTTP/1.1", "");
+        let Query(search) = Query::<Search>::from_request(&req).unwrap();
+        assert_eq!(search.symbol, vec!["AAPL".to_string()]);
+        // The last of repeated scalars wins
+        assert_eq!(search.limit, Some(10));
+        assert_eq!(search.interval, Interval::Daily);
+    }
+
+    #[test]
+    fn rejects_unusable_queries_with_400() {
+        for query in [
+            "symbol=AAPL&ratio=1&adjusted=true",
+            "symbol=AAPL&ratio=high&adjusted=true&interval=daily",
+            "symbol=AAPL&ratio=1&adjusted=yes&interval=daily",
+            "symbol=AAPL&ratio=1&adjusted=true&interval=hourly",
+            "symbol=AAPL&limit=-1&ratio=1&adjusted=true&interval=daily",
+        ] {
+            let req = request(&format!("GET /quotes?{} HTTP/1.1", query), "");
+            assert_eq!(status(Query::<Search>::from_request(&req)), 400, "for {}", query);
+        }
+    }
+
+    #[test]
+    fn reads_path_parameters() {
+        let req = request("GET /quotes/AAPL/7 HTTP/1.1", "").with_params(HashMap::from([
+            ("ticker".to_string(), "AAPL".to_string()),
+            ("days".to_string(), "7".to_string()),
+        ]));
+
+        #[derive(Deserialize)]
+        struct Params {
+            ticker: String,
+            days: u16,
+        }
+        let Path(params) = Path::<Params>::from_request(&req).unwrap();
+        assert_eq!((params.ticker.as_str(), params.days), ("AAPL", 7));
+
+        // A lone value needs no struct, but more than one cannot be picked from
+        assert_eq!(status(Path::<String>::from_request(&req)), 400);
+        let req = req.with_params(HashMap::from([("ticker".to_string(), "AAPL".to_string())]));
+        let Path(ticker) = Path::<String>::from_request(&req).unwrap();
+        assert_eq!(ticker, "AAPL");
+    }
+
+    #[test]
+    fn reads_forms_into_structs() {
+        let req = form("application/x-www-form-urlencoded; charset=UTF-8", "username=j%C3%B6rg+b&remember=true");
+        let Form(login) = Form::<Login>::from_request(&req).unwrap();
+        assert_eq!(login, Login { username: "jörg b".to_string(), remember: Some(true) });
+
+        let req = form("Application/X-WWW-Form-Urlencoded", "username=alice");
+        let Form(login) = Form::<Login>::from_request(&req).unwrap();
+        assert_eq!(login, Login { username: "alice".to_string(), remember: None });
+    }
+
+    #[test]
+    fn rejects_forms_by_what_is_wrong() {
+        assert_eq!(status(Form::<Login>::from_request(&form("application/json", "username=alice"))), 415);
+        assert_eq!(status(Form::<Login>::from_request(&request("POST /login HTTP/1.1", "username=alice"))), 415);
+
+        let urlencoded = "application/x-www-form-urlencoded";
+        assert_eq!(status(Form::<Login>::from_request(&form(urlencoded, "remember=true"))), 422);
+        assert_eq!(status(Form::<Login>::from_request(&form(urlencoded, "username=alice&remember=maybe"))), 422);
+        assert_eq!(status(Form::<Login>::from_request(&form(urlencoded, "username=%ZZ"))), 400);
+    }
+
+    #[test]
+    fn reads_and_rejects_json() {
+        let req = form("application/vnd.api+json", r#"{"username":"alice","remember":false}"#);
+        let Json(login) = Json::<Login>::from_request(&req).unwrap();
+        assert_eq!(login, Login { username: "alice".to_string(), remember: Some(false) });
+
+        assert_eq!(status(Json::<Login>::from_request(&form("text/plain", r#"{"username":"alice"}"#))), 415);
+        assert_eq!(status(Json::<Login>::from_request(&form("application/json", r#"{"username":"alice""#))), 400);
+        assert_eq!(status(Json::<Login>::from_request(&form("application/json", "not json"))), 400);
+        assert_eq!(status(Json::<Login>::from_request(&form("application/json", r#"{"remember":true}"#))), 422);
+        assert_eq!(status(Json::<Login>::from_request(&form("application/json", r#"{"username":7}"#))), 422);
+    }
+}
@@ -0,0 +1,211 @@
+use std::fmt;
+
+use crate::server::methods::is_token;
+use crate::utils::error::ApplicationError;
+
+// Header fields in the order they were added. Names keep the casing they were
+// given but are compared case-insensitively, and a name may carry several
+// values (`Set-Cookie` has to be sent once per cookie). Nothing that could
+// end a header line early gets in, so values taken from user input cannot
+// inject extra headers into a response.
+#[derive(Clone, Default)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The first value given for `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // All values of a list-valued field joined into one, which is how
+    // RFC 9110 says repeated fields like `Connection` are to be read
+    pub fn get_combined(&self, name: &str) -> Option<String> {
+        let values: Vec<&str> = self.get_all(name).collect();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(", "))
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    // Replaces every value of `name`, keeping the field where it first appeared
+    pub fn insert(&mut self, name: &str, value: &str) -> Result<(), ApplicationError> {
+        validate(name, value)?;
+
+        // Earlier entries cannot have matched, so the first one's slot is
+        // still the same after the rest are removed
+        let position = self.entries.iter().position(|(key, _)| key.eq_ignore_ascii_case(name));
+        self.remove(name);
+
+        let entry = (name.to_string(), value.to_string());
+        match position {
+            Some(position) => self.entries.insert(position, entry),
+            None => self.entries.push(entry),
+        }
+
+        Ok(())
+    }
+
+    pub fn append(&mut self, name: &str, value: &str) -> Result<(), ApplicationError> {
+        validate(name, value)?;
+        self.entries.push((name.to_string(), value.to_string()));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        self.entries.len() != before
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+// Fields that carry credentials: passwords, API keys, bearer tokens and
+// session IDs. Their values never show up in debug output, so requests and
+// responses can be logged as they are.
+const SENSITIVE_HEADERS: [&str; 6] = ["authorization", "proxy-authorization", "cookie", "set-cookie", "x-api-key", "x-csrf-token"];
+
+impl fmt::Debug for HeaderMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries: Vec<(&str, &str)> = self
+            .iter()
+            .map(|(name, value)| {
+                let sensitive = SENSITIVE_HEADERS.iter().any(|sensitive| name.eq_ignore_ascii_case(sensitive));
+                (name, if sensitive { "[redacted]" } else { value })
+            })
+            .collect();
+
+        f.debug_struct("HeaderMap").field("entries", &entries).finish()
+    }
+}
+
+fn validate(name: &str, value: &str) -> Result<(), ApplicationError> {
+    if !is_token(name) {
+        return Err(ApplicationError::InvalidHeader(format!("'{}' is not a valid header name", name)));
+    }
+
+    if value.contains(['\r', '\n', '\0']) {
+        return Err(ApplicationError::InvalidHeader(format!("value of '{}' contains a line break", name)));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::response::Response;
+
+    fn map(entries: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in entries {
+            headers.append(name, value).unwrap();
+        }
+        headers
+    }
+
+    fn entries(headers: &HeaderMap) -> Vec<(&str, &str)> {
+        headers.iter().collect()
+    }
+
+    #[test]
+    fn rejects_line_breaks_and_bad_names() {
+        let mut headers = HeaderMap::new();
+
+        for value in ["a\r\nSet-Cookie: admin=1", "a\nb", "a\rb", "a\0b"] {
+            assert!(headers.insert("X-Note", value).is_err(), "accepted {:?}", value);
+            assert!(headers.append("X-Note", value).is_err(), "accepted {:?}", value);
+        }
+        for name in ["", "X Note", "X-Note:", "X-Note\r\n"] {
+            assert!(headers.insert(name, "value").is_err(), "accepted {:?}", name);
+        }
+        assert!(headers.is_empty());
+
+        // A response drops the header instead of sending it
+        let response = Response::new(200, "OK").with_header("Location", "/\r\nSet-Cookie: admin=1");
+        assert!(!response.headers().contains("Location"));
+        assert!(!response.headers().contains("Set-Cookie"));
+    }
+
+    #[test]
+    fn looks_names_up_in_any_case() {
+        let headers = map(&[("Content-Type", "text/html"), ("x-request-id", "abc")]);
+
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some("text/html"));
+        assert_eq!(headers.get("X-Request-Id"), Some("abc"));
+        assert!(headers.contains("X-REQUEST-ID"));
+        assert_eq!(headers.get("Content-Length"), None);
+
+        // The casing given is the one sent
+        assert_eq!(entries(&headers), vec![("Content-Type", "text/html"), ("x-request-id", "abc")]);
+    }
+
+    #[test]
+    fn append_keeps_every_value_in_order() {
+        let headers = map(&[("Set-Cookie", "a=1"), ("Vary", "Accept"), ("set-cookie", "b=2"), ("Connection", "keep-alive")]);
+
+        assert_eq!(headers.get("Set-Cookie"), Some("a=1"));
+        assert_eq!(headers.get_all("SET-COOKIE").collect::<Vec<_>>(), vec!["a=1", "b=2"]);
+        assert_eq!(headers.len(), 4);
+
+        let headers = map(&[("Connection", "keep-alive"), ("connection", "Upgrade")]);
+        assert_eq!(headers.get_combined("Connection").as_deref(), Some("keep-alive, Upgrade"));
+        assert_eq!(headers.get_combined("Upgrade"), None);
+    }
+
+    #[test]
+    fn insert_replaces_every_value_in_place() {
+        let mut headers = map(&[("Server", "x"), ("Set-Cookie", "a=1"), ("Vary", "Accept"), ("set-cookie", "b=2")]);
+
+        headers.insert("SET-COOKIE", "c=3").unwrap();
+        assert_eq!(entries(&headers), vec![("Server", "x"), ("SET-COOKIE", "c=3"), ("Vary", "Accept")]);
+
+        headers.insert("Date", "today").unwrap();
+        assert_eq!(headers.iter().last(), Some(("Date", "today")));
+
+        assert!(headers.remove("server"));
+        assert!(!headers.remove("server"));
+        assert_eq!(headers.len(), 3);
+    }
+
+    #[test]
+    fn debug_output_hides_credentials() {
+        let headers = map(&[("Authorization", "Bearer secret"), ("cookie", "session=secret"), ("Accept", "text/html")]);
+
+        let debug = format!("{:?}", headers);
+        assert!(!debug.contains("secret"), "{}", debug);
+        assert!(debug.contains("text/html"), "{}", debug);
+    }
+}
@@ -0,0 +1,402 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use h2::RecvStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::time;
+
+use crate::server::config::ServerConfig;
+use crate::server::headers::HeaderMap;
+use crate::server::methods::HttpMethod;
+use crate::server::request::{parse_url, Request};
+use crate::server::version::HttpVersion;
+use crate::utils::error::ApplicationError;
+
+// What an HTTP/2 client with prior knowledge sends before anything else
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// What to offer through ALPN, most preferred first. A client that picks "h2"
+// is served HTTP/2 on that connection, any other HTTP/1.
+pub fn alpn_protocols(http2_enabled: bool) -> &'static [&'static [u8]] {
+    if http2_enabled {
+        &[b"h2", b"http/1.1", b"http/1.0"]
+    } else {
+        &[b"http/1.1", b"http/1.0"]
+    }
+}
+
+// Reads from a plain connection until its first bytes either match the
+// HTTP/2 preface or rule it out. The bytes read are handed back so they can
+// be replayed to whichever protocol handles the connection. `None` means the
+// client went quiet for longer than `timeout`.
+pub async fn sniff_preface<S>(stream: &mut S, timeout: time::Duration) -> io::Result<Option<(bool, Vec<u8>)>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buffer = Vec::with_capacity(PREFACE.len());
+
+    while buffer.len() < PREFACE.len() && PREFACE.starts_with(&buffer) {
+        let mut chunk = [0; PREFACE.len()];
+        let wanted = PREFACE.len() - buffer.len();
+
+        let read = match time::timeout(timeout, stream.read(&mut chunk[..wanted])).await {
+            Ok(read) => read?,
+            Err(_) => return Ok(None),
+        };
+        if read == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    Ok(Some((buffer == PREFACE, buffer)))
+}
+
+// A stream with some bytes already read off it, which are read again first
+pub struct Rewind<S> {
+    prefix: Vec<u8>,
+    position: usize,
+    inner: S,
+}
+
+impl<S> Rewind<S> {
+    pub fn new(prefix: Vec<u8>, inner: S) -> Self {
+        Self { prefix, position: 0, inner }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Rewind<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.position < self.prefix.len() {
+            let remaining = &self.prefix[self.position..];
+            let count = remaining.len().min(buf.remaining());
+            buf.put_slice(&remaining[..count]);
+            self.position += count;
+            return Poll::Ready(Ok(()));
+        }
+
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Rewind<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// Turns an HTTP/2 request into the same `Request` an HTTP/1 connection
+// produces, so routes cannot tell the two apart. The body is read in full,
+// within the same size and time limits as over HTTP/1.
+pub async fn read_request(request: http::Request<RecvStream>, config: &ServerConfig) -> Result<Request, ApplicationError> {
+    let (parts, mut body) = request.into_parts();
+
+    let method = HttpMethod::try_from(parts.method.as_str())?;
+    let target = parts.uri.path_and_query().map_or("/", |target| target.as_str());
+    let (path, query_params) = parse_url(target)?;
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in &parts.headers {
+        headers.append(name.as_str(), String::from_utf8_lossy(value.as_bytes()).trim())?;
+    }
+    // HTTP/2 carries the host in the :authority pseudo-header instead
+    if !headers.contains("host")
+        && let Some(authority) = parts.uri.authority()
+    {
+        headers.insert("host", authority.as_str())?;
+    }
+
+    let read_body = async {
+        let mut bytes = Vec::new();
+
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk?;
+            if bytes.len() + chunk.len() > config.max_body_size {
+                return Err(ApplicationError::PayloadTooLarge);
+            }
+            // Reopens the window for the data just taken in
+            let _ = body.flow_control().release_capacity(chunk.len());
+            bytes.extend_from_slice(&chunk);
+        }
+
+        let mut trailers = HeaderMap::new();
+        if let Some(fields) = body.trailers().await? {
+            for (name, value) in &fields {
+                trailers.append(name.as_str(), String::from_utf8_lossy(value.as_bytes()).trim())?;
+            }
+        }
+
+        Ok((bytes, trailers))
+    };
+
+    let (bytes, trailers) = time::timeout(config.read_timeout, read_body)
+        .await
+        .map_err(|_| ApplicationError::RequestTimeout)??;
+
+    Ok(Request::new(method, path, HttpVersion::Http2, headers, query_params, bytes).with_trailers(trailers))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use bytes::Bytes;
+    use rustls::RootCertStore;
+    use rustls::crypto::ring::default_provider;
+    use rustls::pki_types::{PrivateKeyDer, ServerName};
+    use tokio::io::{duplex, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::client::TlsStream;
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+    use super::*;
+    use crate::server::reader::RequestReader;
+    use crate::server::response::Response;
+    use crate::server::route::Route;
+    use crate::server::router::Router;
+    use crate::server::server::HttpServer;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn sniff(sent: &[&[u8]]) -> Option<(bool, Vec<u8>)> {
+        let (mut client, mut server) = duplex(1024);
+        for part in sent {
+            client.write_all(part).await.unwrap();
+        }
+        drop(client);
+        sniff_preface(&mut server, TIMEOUT).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn recognizes_the_http2_preface() {
+        let frames = [PREFACE, b"\x00\x00\x00\x04\x00\x00\x00\x00\x00"].concat();
+        assert_eq!(sniff(&[&frames]).await, Some((true, PREFACE.to_vec())));
+
+        // However the preface is split up
+        let (first, rest) = PREFACE.split_at(5);
+        assert_eq!(sniff(&[first, rest]).await, Some((true, PREFACE.to_vec())));
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_the_preface_as_soon_as_it_cannot_match() {
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let (is_http2, read) = sniff(&[request]).await.unwrap();
+        assert!(!is_http2);
+        assert!(request.starts_with(&read) && read.len() <= PREFACE.len());
+
+        // Shorter than the preface, and answered without waiting for more
+        assert_eq!(sniff(&[b"GET / HTTP/1.0\r\n\r\n"]).await, Some((false, b"GET / HTTP/1.0\r\n\r\n".to_vec())));
+        assert_eq!(sniff(&[b"PRI * HTTP/1.1"]).await, Some((false, b"PRI * HTTP/1.1".to_vec())));
+        assert_eq!(sniff(&[]).await, Some((false, Vec::new())));
+    }
+
+    #[tokio::test]
+    async fn stops_sniffing_when_the_client_goes_quiet() {
+        let (_client, mut server) = duplex(1024);
+        assert_eq!(sniff_preface(&mut server, Duration::from_millis(20)).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn replays_sniffed_bytes_to_the_http1_reader() {
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(b"POST /orders?id=7 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello").await.unwrap();
+
+        let (is_http2, prefix) = sniff_preface(&mut server, TIMEOUT).await.unwrap().unwrap();
+        assert!(!is_http2 && !prefix.is_empty());
+
+        let config = ServerConfig::default();
+        let mut reader = RequestReader::new(Rewind::new(prefix, server), &config);
+        let request = reader.read_request().await.unwrap().unwrap();
+        assert_eq!(request.path(), "/orders");
+        assert_eq!(request.headers().get("host"), Some("localhost"));
+        assert_eq!(request.body(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn rewind_hands_out_the_prefix_in_small_reads_and_writes_through() {
+        let (mut client, server) = duplex(1024);
+        client.write_all(b" world").await.unwrap();
+        let mut rewind = Rewind::new(b"hello".to_vec(), server);
+
+        let mut read = Vec::new();
+        let mut byte = [0; 3];
+        while read.len() < 11 {
+            let n = rewind.read(&mut byte).await.unwrap();
+            read.extend_from_slice(&byte[..n]);
+        }
+        assert_eq!(read, b"hello world");
+
+        rewind.write_all(b"reply").await.unwrap();
+        let mut reply = [0; 5];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"reply");
+    }
+
+    // Answers with the size of the body and the Host the request arrived with
+    struct Upload;
+
+    #[async_trait::async_trait]
+    impl Route for Upload {
+        async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+            let host = req.headers().get("host").unwrap_or("none");
+            Ok(Response::new(200, "OK").with_text_body(&format!("{} bytes for {}", req.body().len(), host)))
+        }
+    }
+
+    // An HTTP/2 connection, without TLS, to a server with these limits
+    async fn h2_client(max_header_size: usize, max_body_size: usize) -> h2::client::SendRequest<Bytes> {
+        let mut router = Router::new();
+        router.add_route(HttpMethod::POST, "/upload", Arc::new(Upload)).unwrap();
+        let config = ServerConfig { max_header_size, max_body_size, ..ServerConfig::default() };
+        let server = Arc::new(HttpServer::new(router, config));
+
+        let (client_end, server_end) = duplex(64 * 1024);
+        let peer_addr = SocketAddr::from(([127, 0, 0, 1], 40000));
+        tokio::spawn(async move {
+            let _ = server.handle_h2_connection(server_end, peer_addr).await;
+        });
+
+        let (send_request, connection) = h2::client::handshake(client_end).await.unwrap();
+        tokio::spawn(connection);
+        send_request.ready().await.unwrap()
+    }
+
+    async fn upload(send_request: &mut h2::client::SendRequest<Bytes>, header: &str, body: usize) -> Result<(u16, String), h2::Error> {
+        let request = http::Request::post("http://api.example.test/upload").header("x-note", header).body(()).unwrap();
+        let (response, mut send_body) = send_request.send_request(request, false)?;
+        send_body.send_data(Bytes::from(vec![b'x'; body]), true)?;
+
+        let response = response.await?;
+        let status = response.status().as_u16();
+        let mut body = response.into_body();
+        let mut text = Vec::new();
+        while let Some(chunk) = body.data().await {
+            text.extend_from_slice(&chunk?);
+        }
+        Ok((status, String::from_utf8(text).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn reads_h2_requests_within_the_limits() {
+        let mut send_request = h2_client(1024, 1024).await;
+
+        let (status, body) = upload(&mut send_request, "short", 1024).await.unwrap();
+        assert_eq!(status, 200);
+        // The host comes from the :authority pseudo-header
+        assert_eq!(body, "1024 bytes for api.example.test");
+    }
+
+    #[tokio::test]
+    async fn rejects_h2_bodies_over_the_limit() {
+        let mut send_request = h2_client(1024, 1024).await;
+
+        let (status, _) = upload(&mut send_request, "short", 1025).await.unwrap();
+        assert_eq!(status, 413);
+
+        // The connection itself is still usable
+        let mut send_request = send_request.ready().await.unwrap();
+        assert_eq!(upload(&mut send_request, "short", 10).await.unwrap().0, 200);
+    }
+
+    #[tokio::test]
+    async fn rejects_h2_headers_over_the_limit() {
+        let mut send_request = h2_client(1024, 1024).await;
+
+        // h2 answers this itself, before the request reaches `read_request`
+        let (status, _) = upload(&mut send_request, &"x".repeat(2048), 10).await.unwrap();
+        assert_eq!(status, 431);
+    }
+
+    // An HTTPS server with no routes, so every request ends in a 404, that
+    // offers h2 if `http2_enabled`, and a client config that trusts it
+    async fn start_https_server(http2_enabled: bool) -> (SocketAddr, rustls::ClientConfig) {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivateKeyDer::Pkcs8(generated.key_pair.serialize_der().into());
+
+        let mut server_config = rustls::ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![generated.cert.der().clone()], key)
+            .unwrap();
+        server_config.alpn_protocols = alpn_protocols(http2_enabled).iter().map(|protocol| protocol.to_vec()).collect();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut config = ServerConfig::default();
+        config.http2.enabled = http2_enabled;
+        let server = Arc::new(HttpServer::new(Router::new(), config));
+        tokio::spawn(server.serve(listener, Some(TlsAcceptor::from(Arc::new(server_config)))));
+
+        let mut roots = RootCertStore::empty();
+        roots.add(generated.cert.der().clone()).unwrap();
+        let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        (addr, client_config)
+    }
+
+    async fn connect(addr: SocketAddr, mut client_config: rustls::ClientConfig, alpn: &[&[u8]]) -> TlsStream<TcpStream> {
+        client_config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        TlsConnector::from(Arc::new(client_config)).connect(server_name, stream).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_http2_when_the_client_picks_h2() {
+        let (addr, client_config) = start_https_server(true).await;
+        let stream = connect(addr, client_config, &[b"h2", b"http/1.1"]).await;
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+        let (send_request, connection) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+
+        let mut send_request = send_request.ready().await.unwrap();
+        let request = http::Request::get("https://localhost/no/such/page").body(()).unwrap();
+        let (response, _) = send_request.send_request(request, true).unwrap();
+        assert_eq!(response.await.unwrap().status(), 404);
+    }
+
+    #[tokio::test]
+    async fn serves_http1_otherwise() {
+        let (addr, client_config) = start_https_server(true).await;
+
+        for alpn in [&[&b"http/1.1"[..]][..], &[]] {
+            let mut stream = connect(addr, client_config.clone(), alpn).await;
+            stream.write_all(b"GET /no/such/page HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await.unwrap();
+            assert!(response.starts_with(b"HTTP/1.1 404"), "ALPN {:?} got {:?}", alpn, String::from_utf8_lossy(&response));
+        }
+    }
+
+    #[tokio::test]
+    async fn offers_h2_only_when_enabled() {
+        let (addr, client_config) = start_https_server(true).await;
+        // The server's preference decides
+        let stream = connect(addr, client_config, &[b"http/1.1", b"h2"]).await;
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+        let (addr, client_config) = start_https_server(false).await;
+        let mut stream = connect(addr, client_config, &[b"h2", b"http/1.1"]).await;
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+        stream.write_all(b"GET /no/such/page HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 404"), "{:?}", String::from_utf8_lossy(&response));
+    }
+}
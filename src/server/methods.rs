@@ -1,23 +1,62 @@
 use crate::utils::error::ApplicationError;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum HttpMethod {
     GET,
+    HEAD,
     POST,
     PUT,
     DELETE,
+    CONNECT,
+    OPTIONS,
+    TRACE,
+    PATCH,
+    // Any other syntactically valid method token, e.g. WebDAV's PROPFIND
+    Extension(String),
+}
+
+impl HttpMethod {
+    pub fn as_str(&self) -> &str {
+        match self {
+            HttpMethod::GET => "GET",
+            HttpMethod::HEAD => "HEAD",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::CONNECT => "CONNECT",
+            HttpMethod::OPTIONS => "OPTIONS",
+            HttpMethod::TRACE => "TRACE",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::Extension(method) => method,
+        }
+    }
 }
 
 impl TryFrom<&str> for HttpMethod {
     type Error = ApplicationError;
 
+    // Method names are case-sensitive, so "get" is an extension method
+    // rather than a misspelled GET.
     fn try_from(method_str: &str) -> Result<Self, Self::Error> {
         match method_str {
             "GET" => Ok(HttpMethod::GET),
+            "HEAD" => Ok(HttpMethod::HEAD),
             "POST" => Ok(HttpMethod::POST),
             "PUT" => Ok(HttpMethod::PUT),
             "DELETE" => Ok(HttpMethod::DELETE),
+            "CONNECT" => Ok(HttpMethod::CONNECT),
+            "OPTIONS" => Ok(HttpMethod::OPTIONS),
+            "TRACE" => Ok(HttpMethod::TRACE),
+            "PATCH" => Ok(HttpMethod::PATCH),
+            _ if is_token(method_str) => Ok(HttpMethod::Extension(method_str.to_string())),
             _ => Err(ApplicationError::InvalidHttpMethod(method_str.to_string())),
         }
     }
-}
\ No newline at end of file
+}
+
+// RFC 9110 `token`: one or more visible ASCII characters other than delimiters
+pub(crate) fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
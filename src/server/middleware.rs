@@ -0,0 +1,146 @@
+use std::sync::Arc;
+use crate::server::request::Request;
+use crate::server::response::Response;
+use crate::server::route::Route;
+use crate::utils::error::ApplicationError;
+
+// Runs around a route. An implementation either answers the request itself
+// (short-circuiting the rest of the chain) or passes it on with `next.run`,
+// and can inspect or change the response that comes back.
+#[async_trait::async_trait]
+pub trait Middleware: Send + Sync {
+    async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, ApplicationError>;
+}
+
+// The remainder of a chain: the middleware registered after the current one,
+// followed by the route at the end.
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+    endpoint: &'a dyn Route,
+}
+
+impl<'a> Next<'a> {
+    pub fn new(middleware: &'a [Arc<dyn Middleware>], endpoint: &'a dyn Route) -> Self {
+        Self { middleware, endpoint }
+    }
+
+    pub async fn run(self, req: Request) -> Result<Response, ApplicationError> {
+        match self.middleware.split_first() {
+            Some((current, rest)) => current.handle(req, Next::new(rest, self.endpoint)).await,
+            None => self.endpoint.handle(req).await,
+        }
+    }
+}
+
+// A route wrapped in its own middleware, used for route groups
+pub struct Layered {
+    route: Arc<dyn Route>,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Layered {
+    pub fn new(route: Arc<dyn Route>, middleware: Vec<Arc<dyn Middleware>>) -> Self {
+        Self { route, middleware }
+    }
+}
+
+#[async_trait::async_trait]
+impl Route for Layered {
+    async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+        Next::new(&self.middleware, self.route.as_ref()).run(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::*;
+    use crate::server::methods::HttpMethod;
+    use crate::server::router::Router;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    // Notes when a request passes through on its way in and out
+    struct Recorder {
+        name: &'static str,
+        log: Log,
+    }
+
+    #[async_trait::async_trait]
+    impl Middleware for Recorder {
+        async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, ApplicationError> {
+            self.log.lock().unwrap().push(format!("{} in", self.name));
+            let response = next.run(req).await?;
+            self.log.lock().unwrap().push(format!("{} out", self.name));
+            Ok(response.with_appended_header("X-Seen-By", self.name))
+        }
+    }
+
+    struct Deny;
+
+    #[async_trait::async_trait]
+    impl Middleware for Deny {
+        async fn handle(&self, _req: Request, _next: Next<'_>) -> Result<Response, ApplicationError> {
+            Ok(Response::new(403, "Forbidden"))
+        }
+    }
+
+    struct Endpoint {
+        log: Log,
+    }
+
+    #[async_trait::async_trait]
+    impl Route for Endpoint {
+        async fn handle(&self, _req: Request) -> Result<Response, ApplicationError> {
+            self.log.lock().unwrap().push("route".to_string());
+            Ok(Response::new(200, "OK"))
+        }
+    }
+
+    fn recorder(name: &'static str, log: &Log) -> Arc<dyn Middleware> {
+        Arc::new(Recorder { name, log: Arc::clone(log) })
+    }
+
+    fn request() -> Request {
+        Request::try_from(&b"GET / HTTP/1.1\r\n\r\n"[..]).unwrap()
+    }
+
+    fn entries(log: &Log) -> Vec<String> {
+        log.lock().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn first_layer_is_the_outermost() {
+        let log = Log::default();
+        let endpoint = Endpoint { log: Arc::clone(&log) };
+        let middleware = [recorder("outer", &log), recorder("inner", &log)];
+
+        let response = Next::new(&middleware, &endpoint).run(request()).await.unwrap();
+
+        assert_eq!(entries(&log), ["outer in", "inner in", "route", "inner out", "outer out"]);
+        assert_eq!(response.headers().get_all("X-Seen-By").collect::<Vec<_>>(), ["inner", "outer"]);
+
+        // The same holds for layers added to a router
+        let log = Log::default();
+        let mut router = Router::new();
+        router.layer(recorder("first", &log));
+        router.layer(recorder("second", &log));
+        router.add_route(HttpMethod::GET, "/", Arc::new(Endpoint { log: Arc::clone(&log) })).unwrap();
+
+        router.route(request()).await.unwrap();
+        assert_eq!(entries(&log), ["first in", "second in", "route", "second out", "first out"]);
+    }
+
+    #[tokio::test]
+    async fn a_layer_can_answer_without_calling_next() {
+        let log = Log::default();
+        let endpoint: Arc<dyn Route> = Arc::new(Endpoint { log: Arc::clone(&log) });
+        let layered = Layered::new(endpoint, vec![recorder("outer", &log), Arc::new(Deny), recorder("inner", &log)]);
+
+        let response = layered.handle(request()).await.unwrap();
+
+        assert_eq!(response.status_code(), 403);
+        assert_eq!(entries(&log), ["outer in", "outer out"]);
+        assert_eq!(response.header("X-Seen-By"), Some("outer"));
+    }
+}
@@ -1,6 +1,21 @@
+#[allow(clippy::module_inception)]
 pub mod server;
+pub mod config;
 pub mod methods;
+pub mod version;
+pub mod headers;
+pub mod cookie;
+pub mod extensions;
 pub mod request;
+pub mod reader;
 pub mod response;
 pub mod router;
-pub mod route;
\ No newline at end of file
+pub mod pattern;
+pub mod tree;
+pub mod route;
+pub mod middleware;
+pub mod urlencoded;
+pub mod extract;
+pub mod tls;
+pub mod http2;
+pub mod compression;
@@ -0,0 +1,73 @@
+use crate::server::tree::split_path;
+use crate::utils::error::ApplicationError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Static(String),
+    Param(String),
+    Wildcard(String),
+}
+
+// A route path such as `/symbols/{ticker}/history` or `/static/*rest`.
+// `{name}` captures exactly one segment, `*name` captures the remainder of
+// the path and must come last.
+#[derive(Debug, Clone)]
+pub struct PathPattern {
+    pattern: String,
+    segments: Vec<Segment>,
+}
+
+impl PathPattern {
+    pub fn parse(pattern: &str) -> Result<Self, ApplicationError> {
+        let invalid = |reason: &str| ApplicationError::InvalidRoutePattern(format!("{}: {}", pattern, reason));
+
+        if !pattern.starts_with('/') {
+            return Err(invalid("must start with '/'"));
+        }
+
+        let mut segments = Vec::new();
+        let mut names: Vec<String> = Vec::new();
+
+        for part in split_path(pattern) {
+            if matches!(segments.last(), Some(Segment::Wildcard(_))) {
+                return Err(invalid("a wildcard must be the last segment"));
+            }
+
+            let segment = if let Some(name) = part.strip_prefix('{') {
+                let name = name.strip_suffix('}').ok_or_else(|| invalid("unclosed '{'"))?;
+                Segment::Param(name.to_string())
+            } else if let Some(name) = part.strip_prefix('*') {
+                Segment::Wildcard(name.to_string())
+            } else if part.contains(['{', '}', '*']) {
+                return Err(invalid("parameters must span a whole segment"));
+            } else {
+                Segment::Static(part.to_string())
+            };
+
+            if let Segment::Param(name) | Segment::Wildcard(name) = &segment {
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    return Err(invalid("parameter names must be non-empty identifiers"));
+                }
+                if names.contains(name) {
+                    return Err(invalid("duplicate parameter name"));
+                }
+                names.push(name.clone());
+            }
+
+            segments.push(segment);
+        }
+
+        Ok(Self {
+            pattern: pattern.to_string(),
+            segments,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+}
@@ -0,0 +1,372 @@
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::time;
+
+use crate::server::config::ServerConfig;
+use crate::server::headers::HeaderMap;
+use crate::server::request::{parse_header, Request};
+use crate::utils::error::ApplicationError;
+
+const READ_CHUNK_SIZE: usize = 4096;
+const HEADER_TERMINATOR: &[u8] = b"\r\n\r\n";
+
+pub struct RequestReader<R> {
+    reader: R,
+    // Bytes read from the socket but not yet consumed by a request. Pipelined
+    // requests stay in here until the previous response has been written.
+    buffer: Vec<u8>,
+    max_header_size: usize,
+    max_body_size: usize,
+    keep_alive_timeout: Duration,
+    read_timeout: Duration,
+}
+
+impl<R: AsyncRead + Unpin> RequestReader<R> {
+    pub fn new(reader: R, config: &ServerConfig) -> Self {
+        Self {
+            reader,
+            buffer: Vec::with_capacity(READ_CHUNK_SIZE),
+            max_header_size: config.max_header_size,
+            max_body_size: config.max_body_size,
+            keep_alive_timeout: config.keep_alive_timeout,
+            read_timeout: config.read_timeout,
+        }
+    }
+
+    // Returns `None` when the client closed the connection or stayed idle for
+    // longer than the keep-alive timeout before starting the next request.
+    pub async fn read_request(&mut self) -> Result<Option<Request>, ApplicationError> {
+        let head_len = match self.read_head().await? {
+            Some(head_len) => head_len,
+            None => return Ok(None),
+        };
+
+        let head: Vec<u8> = self.buffer.drain(..head_len).collect();
+        let request = Request::try_from(head.as_slice())?;
+
+        if is_chunked(&request)? {
+            if request.headers().contains("content-length") {
+                // Conflicting framing is a classic request smuggling vector
+                return Err(ApplicationError::InvalidRequestFormat);
+            }
+
+            let (body, trailers) = self.read_chunked_body().await?;
+            return Ok(Some(request.with_body(body).with_trailers(trailers)));
+        }
+
+        let content_length = content_length(&request)?;
+        if content_length > self.max_body_size {
+            return Err(ApplicationError::PayloadTooLarge);
+        }
+
+        self.fill_to(content_length).await?;
+        let body = self.buffer.drain(..content_length).collect();
+        Ok(Some(request.with_body(body)))
+    }
+
+    async fn read_chunked_body(&mut self) -> Result<(Vec<u8>, HeaderMap), ApplicationError> {
+        let mut body = Vec::new();
+
+        loop {
+            // Chunk extensions after ';' carry nothing we act on
+            let line = self.read_line().await?;
+            let size_str = line.split(';').next().unwrap_or_default().trim();
+            let size = parse_chunk_size(size_str)?;
+
+            if size == 0 {
+                break;
+            }
+
+            if body.len().saturating_add(size) > self.max_body_size {
+                return Err(ApplicationError::PayloadTooLarge);
+            }
+
+            self.fill_to(size + 2).await?;
+            body.extend(self.buffer.drain(..size));
+
+            if !self.buffer.starts_with(b"\r\n") {
+                return Err(ApplicationError::InvalidRequestFormat);
+            }
+            self.buffer.drain(..2);
+        }
+
+        let mut trailers = HeaderMap::new();
+        let mut trailers_size = 0;
+
+        loop {
+            let line = self.read_line().await?;
+            if line.is_empty() {
+                break;
+            }
+
+            trailers_size += line.len();
+            if trailers_size > self.max_header_size {
+                return Err(ApplicationError::HeadersTooLarge);
+            }
+
+            let (key, value) = parse_header(&line)?;
+            trailers.append(&key, &value)?;
+        }
+
+        Ok((body, trailers))
+    }
+
+    async fn read_line(&mut self) -> Result<String, ApplicationError> {
+        let mut searched = 0;
+
+        loop {
+            if let Some(position) = find(&self.buffer, b"\r\n", searched) {
+                let line: Vec<u8> = self.buffer.drain(..position + 2).collect();
+                return Ok(String::from_utf8_lossy(&line[..position]).to_string());
+            }
+
+            if self.buffer.len() > self.max_header_size {
+                return Err(ApplicationError::InvalidRequestFormat);
+            }
+
+            searched = self.buffer.len().saturating_sub(1);
+
+            if self.fill_buffer(self.read_timeout).await? == 0 {
+                return Err(ApplicationError::InvalidRequestFormat);
+            }
+        }
+    }
+
+    async fn read_head(&mut self) -> Result<Option<usize>, ApplicationError> {
+        let mut searched = 0;
+
+        loop {
+            // Clients may send stray line breaks between pipelined requests
+            while self.buffer.starts_with(b"\r\n") {
+                self.buffer.drain(..2);
+            }
+
+            if let Some(position) = find(&self.buffer, HEADER_TERMINATOR, searched) {
+                let head_len = position + HEADER_TERMINATOR.len();
+                if head_len > self.max_header_size {
+                    return Err(ApplicationError::HeadersTooLarge);
+                }
+                return Ok(Some(head_len));
+            }
+
+            if self.buffer.len() > self.max_header_size {
+                return Err(ApplicationError::HeadersTooLarge);
+            }
+
+            // The terminator may straddle two reads, so rescan the tail
+            searched = self.buffer.len().saturating_sub(HEADER_TERMINATOR.len() - 1);
+
+            if self.buffer.is_empty() {
+                match time::timeout(self.keep_alive_timeout, self.read_chunk()).await {
+                    Ok(result) => {
+                        if result? == 0 {
+                            return Ok(None);
+                        }
+                    }
+                    Err(_) => return Ok(None),
+                }
+            } else if self.fill_buffer(self.read_timeout).await? == 0 {
+                return Err(ApplicationError::InvalidRequestFormat);
+            }
+        }
+    }
+
+    async fn fill_to(&mut self, len: usize) -> Result<(), ApplicationError> {
+        while self.buffer.len() < len {
+            if self.fill_buffer(self.read_timeout).await? == 0 {
+                return Err(ApplicationError::InvalidRequestFormat);
+            }
+        }
+        Ok(())
+    }
+
+    async fn fill_buffer(&mut self, timeout: Duration) -> Result<usize, ApplicationError> {
+        time::timeout(timeout, self.read_chunk())
+            .await
+            .map_err(|_| ApplicationError::RequestTimeout)?
+    }
+
+    async fn read_chunk(&mut self) -> Result<usize, ApplicationError> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        let n = self.reader.read(&mut chunk).await?;
+        self.buffer.extend_from_slice(&chunk[..n]);
+        Ok(n)
+    }
+}
+
+fn find(buffer: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    buffer
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| from + position)
+}
+
+fn parse_chunk_size(size_str: &str) -> Result<usize, ApplicationError> {
+    if size_str.is_empty() || !size_str.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ApplicationError::InvalidRequestFormat);
+    }
+
+    usize::from_str_radix(size_str, 16).map_err(|_| ApplicationError::PayloadTooLarge)
+}
+
+// Only "chunked" is understood; any other coding (gzip, deflate, ...) would
+// leave us unable to find the end of the body.
+fn is_chunked(request: &Request) -> Result<bool, ApplicationError> {
+    match request.headers().get_combined("transfer-encoding") {
+        Some(value) => {
+            let codings: Vec<String> = value
+                .split(',')
+                .map(|coding| coding.trim().to_lowercase())
+                .filter(|coding| !coding.is_empty())
+                .collect();
+
+            if codings == ["chunked"] {
+                Ok(true)
+            } else {
+                Err(ApplicationError::UnsupportedTransferEncoding(value))
+            }
+        }
+        None => Ok(false),
+    }
+}
+
+// Repeated Content-Length fields (or a comma-separated list) are only
+// accepted when they all agree; otherwise the body's end is ambiguous.
+fn content_length(request: &Request) -> Result<usize, ApplicationError> {
+    let mut lengths = request
+        .headers()
+        .get_all("content-length")
+        .flat_map(|value| value.split(','))
+        .map(|value| parse_content_length(value.trim()));
+
+    let Some(length) = lengths.next().transpose()? else {
+        return Ok(0);
+    };
+
+    for other in lengths {
+        if other? != length {
+            return Err(ApplicationError::InvalidRequestFormat);
+        }
+    }
+
+    Ok(length)
+}
+
+// Digits only: `parse` would also take a leading '+', which a proxy in front
+// may read differently, and the two would disagree on where the body ends
+fn parse_content_length(value: &str) -> Result<usize, ApplicationError> {
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(ApplicationError::InvalidHeaderFormat);
+    }
+
+    value.parse::<usize>().map_err(|_| ApplicationError::PayloadTooLarge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(raw: &[u8]) -> Result<Option<Request>, ApplicationError> {
+        read_with(raw, &ServerConfig::default()).await
+    }
+
+    async fn read_with(raw: &[u8], config: &ServerConfig) -> Result<Option<Request>, ApplicationError> {
+        RequestReader::new(raw, config).read_request().await
+    }
+
+    const CHUNKED_HEAD: &str = "POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n";
+
+    #[tokio::test]
+    async fn decodes_chunked_body_with_extensions_and_trailers() {
+        let raw = format!("{}5;name=value\r\nhello\r\n7\r\n, world\r\n0\r\nChecksum: abc\r\n\r\n", CHUNKED_HEAD);
+
+        let request = read(raw.as_bytes()).await.unwrap().unwrap();
+        assert_eq!(request.body(), b"hello, world");
+        assert_eq!(request.trailers().get("checksum"), Some("abc"));
+    }
+
+    #[tokio::test]
+    async fn leaves_pipelined_request_after_chunked_body() {
+        let raw = format!("{}3\r\nabc\r\n0\r\n\r\nGET /next HTTP/1.1\r\nHost: localhost\r\n\r\n", CHUNKED_HEAD);
+        let mut reader = RequestReader::new(raw.as_bytes(), &ServerConfig::default());
+
+        assert_eq!(reader.read_request().await.unwrap().unwrap().body(), b"abc");
+        assert_eq!(reader.read_request().await.unwrap().unwrap().path(), "/next");
+        assert!(reader.read_request().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_chunks() {
+        for body in ["z\r\nabc\r\n0\r\n\r\n", "3\r\nabcd\r\n0\r\n\r\n", "\r\nabc\r\n0\r\n\r\n", "3\r\nab"] {
+            let raw = format!("{}{}", CHUNKED_HEAD, body);
+            assert!(
+                matches!(read(raw.as_bytes()).await, Err(ApplicationError::InvalidRequestFormat)),
+                "accepted {:?}",
+                body
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_chunked_body_over_the_size_limit() {
+        let config = ServerConfig { max_body_size: 4, ..ServerConfig::default() };
+        let raw = format!("{}3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n", CHUNKED_HEAD);
+
+        let result = RequestReader::new(raw.as_bytes(), &config).read_request().await;
+        assert!(matches!(result, Err(ApplicationError::PayloadTooLarge)));
+
+        let raw = format!("{}ffffffffffffffffffff\r\n", CHUNKED_HEAD);
+        assert!(matches!(read(raw.as_bytes()).await, Err(ApplicationError::PayloadTooLarge)));
+    }
+
+    #[tokio::test]
+    async fn rejects_ambiguous_framing() {
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n0\r\n\r\n";
+        assert!(matches!(read(raw).await, Err(ApplicationError::InvalidRequestFormat)));
+
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n";
+        assert!(matches!(read(raw).await, Err(ApplicationError::UnsupportedTransferEncoding(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_content_lengths() {
+        for length in ["+3", "-3", "", " ", "3 3", "0x3", "1,2"] {
+            let raw = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\nabc", length);
+            let result = read(raw.as_bytes()).await;
+            assert!(
+                matches!(result, Err(ApplicationError::InvalidHeaderFormat | ApplicationError::InvalidRequestFormat)),
+                "accepted Content-Length {:?}",
+                length
+            );
+        }
+
+        // Repeats that agree are fine
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 3, 3\r\nContent-Length: 3\r\n\r\nabc";
+        assert_eq!(read(raw).await.unwrap().unwrap().body(), b"abc");
+    }
+
+    #[tokio::test]
+    async fn rejects_oversize_heads_and_bodies() {
+        let config = ServerConfig { max_header_size: 64, max_body_size: 8, ..ServerConfig::default() };
+
+        let raw = format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(100));
+        assert!(matches!(read_with(raw.as_bytes(), &config).await, Err(ApplicationError::HeadersTooLarge)));
+
+        // Without a terminator in sight the reader gives up just the same
+        let raw = format!("GET / HTTP/1.1\r\nX-Padding: {}", "a".repeat(100));
+        assert!(matches!(read_with(raw.as_bytes(), &config).await, Err(ApplicationError::HeadersTooLarge)));
+
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\n123456789".to_string();
+        assert!(matches!(read_with(raw.as_bytes(), &config).await, Err(ApplicationError::PayloadTooLarge)));
+
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n".to_string();
+        assert!(matches!(read_with(raw.as_bytes(), &config).await, Err(ApplicationError::PayloadTooLarge)));
+
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n12345\r\n5\r\n67890\r\n0\r\n\r\n".to_string();
+        assert!(matches!(read_with(raw.as_bytes(), &config).await, Err(ApplicationError::PayloadTooLarge)));
+
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 8\r\n\r\n12345678".to_string();
+        assert_eq!(read_with(raw.as_bytes(), &config).await.unwrap().unwrap().body(), b"12345678");
+    }
+}
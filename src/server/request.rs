@@ -1,129 +1,273 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
 use getset::Getters;
+use crate::server::cookie::CookieJar;
+use crate::server::extensions::Extensions;
+use crate::server::headers::HeaderMap;
 use crate::server::methods::HttpMethod;
+use crate::server::urlencoded::{parse_urlencoded, percent_decode};
+use crate::server::version::HttpVersion;
 use crate::utils::error::ApplicationError;
 
-#[derive(Debug, Clone, Getters)]
+const MAX_HEADERS: usize = 100;
+
+#[derive(Clone, Getters)]
 #[getset(get = "pub")]
 pub struct Request {
     method: HttpMethod,
     path: String,
-    headers: HashMap<String, String>,
-    query_params: HashMap<String, String>,
+    version: HttpVersion,
+    headers: HeaderMap,
+    cookies: CookieJar,
+    query_params: QueryParams,
     body: Vec<u8>,
+    trailers: HeaderMap,
+    params: HashMap<String, String>,
+    extensions: Extensions,
+    // The client's address as seen by `listener.accept()`; `None` for
+    // requests that did not come in over a socket
+    #[getset(skip)]
+    peer_addr: Option<SocketAddr>,
 }
 
 impl Request {
     pub fn new(
         method: HttpMethod,
         path: String,
-        headers: HashMap<String, String>,
-        query_params: HashMap<String, String>,
+        version: HttpVersion,
+        headers: HeaderMap,
+        query_params: QueryParams,
         body: Vec<u8>,
     ) -> Self {
-        Self { method, path, headers, query_params, body }
+        let cookies = CookieJar::from_headers(&headers);
+        Self { method, path, version, headers, cookies, query_params, body, trailers: HeaderMap::new(), params: HashMap::new(), extensions: Extensions::new(), peer_addr: None }
     }
-}
 
-impl TryFrom<&str> for Request {
-    type Error = ApplicationError;
+    pub fn with_body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
 
-    fn try_from(request_str: &str) -> Result<Self, Self::Error> {
-        let mut lines = request_str.lines();
+    pub fn with_trailers(mut self, trailers: HeaderMap) -> Self {
+        self.trailers = trailers;
+        self
+    }
 
-        // Parse the request line (e.g., "GET /path?query=value HTTP/1.1")
-        let request_line = lines.next().ok_or(ApplicationError::InvalidFormat)?;
-        let mut parts = request_line.split_whitespace();
+    // Captured by the `{name}` and `*name` segments of the matched route
+    pub fn with_params(mut self, params: HashMap<String, String>) -> Self {
+        self.params = params;
+        self
+    }
 
-        let method_str = parts.next().ok_or(ApplicationError::InvalidRequestLine)?;
-        let url_str = parts.next().ok_or(ApplicationError::InvalidRequestLine)?;
+    pub fn with_peer_addr(mut self, peer_addr: SocketAddr) -> Self {
+        self.peer_addr = Some(peer_addr);
+        self
+    }
 
-        // Parse the HTTP method
-        let method = HttpMethod::try_from(method_str)?;
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
 
-        // Parse the path and query parameters
-        let (path, query_params) = parse_url(url_str)?;
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
 
-        // Parse headers
-        let mut headers = HashMap::new();
-        let mut blank_line_found = false;
+    pub fn with_extension<T: Send + Sync + 'static>(mut self, value: T) -> Self {
+        self.extensions.insert(value);
+        self
+    }
 
-        for line in lines {
-            if line.is_empty() {
-                blank_line_found = true;
-                break;
-            }
+    pub fn extension<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get::<T>()
+    }
 
-            let (key, value) = parse_header(line)?;
-            headers.insert(key, value);
-        }
+    // HTTP/1.1 connections are persistent unless the client opts out, while
+    // HTTP/1.0 clients have to ask for keep-alive explicitly. HTTP/2
+    // connections always are.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.headers.get_combined("connection").map(|value| value.to_lowercase());
+        let has_token = |token: &str| {
+            connection
+                .as_deref()
+                .is_some_and(|value| value.split(',').any(|part| part.trim() == token))
+        };
 
-        if !blank_line_found && !request_str.ends_with("\r\n\r\n") && !request_str.ends_with("\n\n") {
-            // No blank line means no body, but that's OK for some methods
-            if method == HttpMethod::GET || method == HttpMethod::DELETE {
-                return Ok(Request {
-                    method,
-                    path,
-                    headers,
-                    query_params,
-                    body: Vec::new(),
-                });
-            }
+        if has_token("close") {
+            false
+        } else if has_token("keep-alive") {
+            true
+        } else {
+            self.version != HttpVersion::Http10
         }
+    }
+}
+
+// Query string parameters in the order they were given. A key may appear
+// more than once, as in `?symbol=AAPL&symbol=MSFT`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryParams {
+    pairs: Vec<(String, String)>,
+}
 
-        // Extract body (everything after the blank line)
-        let mut body = Vec::new();
-        if blank_line_found {
-            let body_start = request_str.find("\r\n\r\n")
-                .or_else(|| request_str.find("\n\n"))
-                .map(|pos| {
-                    if request_str[pos..].starts_with("\r\n\r\n") {
-                        pos + 4
-                    } else {
-                        pos + 2
-                    }
-                });
-
-            if let Some(start) = body_start {
-                if start < request_str.len() {
-                    body = request_str[start..].as_bytes().to_vec();
-                }
-            }
+impl QueryParams {
+    pub fn new(pairs: Vec<(String, String)>) -> Self {
+        Self { pairs }
+    }
+
+    // The first value given for `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs
+            .iter()
+            .filter(move |(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+impl FromIterator<(String, String)> for QueryParams {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl TryFrom<&[u8]> for Request {
+    type Error = ApplicationError;
+
+    fn try_from(message: &[u8]) -> Result<Self, Self::Error> {
+        let mut header_buffer = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut parsed = httparse::Request::new(&mut header_buffer);
+
+        let head_len = match parsed.parse(message).map_err(parse_error)? {
+            httparse::Status::Complete(head_len) => head_len,
+            httparse::Status::Partial => return Err(ApplicationError::InvalidFormat),
+        };
+
+        // Parse the HTTP method and protocol version
+        let method = HttpMethod::try_from(parsed.method.ok_or(ApplicationError::InvalidRequestLine)?)?;
+        let version = match parsed.version {
+            Some(0) => HttpVersion::Http10,
+            Some(1) => HttpVersion::Http11,
+            _ => return Err(ApplicationError::InvalidRequestLine),
+        };
+
+        // Parse the path and query parameters
+        let url_str = parsed.path.ok_or(ApplicationError::InvalidRequestLine)?;
+        let (path, query_params) = parse_url(url_str)?;
+
+        // Header values are opaque bytes on the wire; anything that is not
+        // valid UTF-8 is kept in lossy form rather than failing the request.
+        let mut headers = HeaderMap::new();
+        for header in parsed.headers.iter() {
+            headers.append(header.name, String::from_utf8_lossy(header.value).trim())?;
         }
 
+        let cookies = CookieJar::from_headers(&headers);
+
+        // Anything after the blank line is the body, byte for byte
+        let body = message[head_len..].to_vec();
+
         Ok(Request {
             method,
             path,
+            version,
             headers,
+            cookies,
             query_params,
             body,
+            trailers: HeaderMap::new(),
+            params: HashMap::new(),
+            extensions: Extensions::new(),
+            peer_addr: None,
         })
     }
 }
 
+// Requests get logged, so the body (a login form's password, say) and the
+// cookies are left out; credential headers are masked by `HeaderMap`
+impl fmt::Debug for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Request")
+            .field("method", &self.method)
+            .field("path", &self.path)
+            .field("version", &self.version)
+            .field("headers", &self.headers)
+            .field("query_params", &self.query_params)
+            .field("body_length", &self.body.len())
+            .field("trailers", &self.trailers)
+            .field("params", &self.params)
+            .field("peer_addr", &self.peer_addr)
+            .finish_non_exhaustive()
+    }
+}
+
+fn parse_error(err: httparse::Error) -> ApplicationError {
+    match err {
+        httparse::Error::HeaderName | httparse::Error::HeaderValue | httparse::Error::NewLine => {
+            ApplicationError::InvalidHeaderFormat
+        }
+        httparse::Error::Version => ApplicationError::InvalidHttpVersion(err.to_string()),
+        httparse::Error::TooManyHeaders => ApplicationError::HeadersTooLarge,
+        _ => ApplicationError::InvalidRequestLine,
+    }
+}
+
+// Splits a request target into its decoded path and query parameters. The
+// fragment never belongs on the wire but is dropped if a client sends one.
+pub(crate) fn parse_url(url_str: &str) -> Result<(String, QueryParams), ApplicationError> {
+    let url_str = url_str.split_once('#').map_or(url_str, |(url, _)| url);
+    let (raw_path, raw_query) = url_str.split_once('?').unwrap_or((url_str, ""));
+
+    // Segments are decoded one by one, so an encoded "%2F" cannot turn into
+    // a separator the router and static files would act on. '+' only means
+    // a space in the query component.
+    let segments = raw_path
+        .split('/')
+        .map(|segment| percent_decode(segment, false))
+        .collect::<Result<Vec<_>, _>>()?;
 
-fn parse_url(url_str: &str) -> Result<(String, HashMap<String, String>), ApplicationError> {
-    let mut query_params = HashMap::new();
-    let parts: Vec<&str> = url_str.split('?').collect();
-    let path = parts[0].to_string();
-
-    if parts.len() > 1 {
-        let query_str = parts[1];
-        for param in query_str.split('&') {
-            let kv: Vec<&str> = param.split('=').collect();
-            if kv.len() == 2 {
-                query_params.insert(kv[0].to_string(), kv[1].to_string());
-            } else if kv.len() == 1 && !kv[0].is_empty() {
-                // Query parameter with no value, e.g., "?param"
-                query_params.insert(kv[0].to_string(), String::new());
-            }
+    // Checked after decoding so that "%2e%2e" cannot slip past as a plain name
+    for segment in &segments {
+        if segment.contains('\0') {
+            return Err(ApplicationError::UrlParseError(format!("'{}' contains a NUL byte", raw_path)));
+        }
+        if segment.contains(['/', '\\']) {
+            return Err(ApplicationError::UrlParseError(format!("'{}' contains an encoded '/' or a backslash", raw_path)));
+        }
+        if segment == ".." {
+            return Err(ApplicationError::UrlParseError(format!("'{}' escapes the root directory", raw_path)));
         }
     }
+    let path = segments.join("/");
+
+    let query_params = QueryParams::new(parse_urlencoded(raw_query)?);
 
     Ok((path, query_params))
 }
 
-fn parse_header(header_line: &str) -> Result<(String, String), ApplicationError> {
+pub(crate) fn parse_header(header_line: &str) -> Result<(String, String), ApplicationError> {
     let parts: Vec<&str> = header_line.splitn(2, ':').collect();
     if parts.len() != 2 {
         return Err(ApplicationError::InvalidHeaderFormat);
@@ -133,4 +277,36 @@ fn parse_header(header_line: &str) -> Result<(String, String), ApplicationError>
     let value = parts[1].trim().to_string();
 
     Ok((key, value))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_path_and_query() {
+        let (path, query) = parse_url("/symbols/BRK%2EB?q=a+b&x=%26#top").unwrap();
+
+        assert_eq!(path, "/symbols/BRK.B");
+        assert_eq!(query.get("q"), Some("a b"));
+        assert_eq!(query.get("x"), Some("&"));
+    }
+
+    #[test]
+    fn rejects_encoded_separators_and_traversal() {
+        for target in ["/css/%2Fetc%2Fhostname", "/AA%2fPL", "/css/..%5C..%5Cwin.ini", "/css/a\\b", "/css/%2e%2e/x", "/a/../b", "/a%00b"] {
+            assert!(matches!(parse_url(target), Err(ApplicationError::UrlParseError(_))), "accepted {}", target);
+        }
+    }
+
+    #[test]
+    fn debug_output_leaves_out_credentials() {
+        let raw = b"POST /login HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer secret-token\r\n\
+                    X-API-Key: secret-key\r\nCookie: session=secret-session\r\n\r\nusername=a&password=secret-password";
+        let request = Request::try_from(&raw[..]).unwrap();
+
+        let logged = format!("{:?}", request);
+        assert!(logged.contains("/login") && logged.contains("localhost"));
+        assert!(!logged.contains("secret"), "{}", logged);
+    }
+}
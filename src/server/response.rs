@@ -1,20 +1,87 @@
-use std::collections::HashMap;
-use std::convert::Into;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use bytes::Bytes;
+use futures_util::future::poll_fn;
+use futures_util::stream::{self, Stream, StreamExt};
+use h2::server::SendResponse;
+use h2::{Reason, SendStream};
+use http::header::{HeaderName, HeaderValue};
 use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::Receiver;
+use tracing::warn;
 
-#[derive(Debug, Clone)]
+use crate::server::compression::{encode, ContentEncoding, Encoder};
+use crate::server::cookie::Cookie;
+use crate::server::headers::HeaderMap;
+use crate::server::version::HttpVersion;
+use crate::utils::error::ApplicationError;
+
+const STREAM_CHUNK_SIZE: usize = 8192;
+
+// Headers that describe an HTTP/1.1 connection and are malformed in HTTP/2
+const CONNECTION_SPECIFIC_HEADERS: [&str; 5] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+#[derive(Debug)]
 pub enum ResponseBody {
     Text(String),
     Json(serde_json::Value),
     Raw(Vec<u8>),
+    // Produced incrementally while the response is being written
+    Stream(BodyStream),
+}
+
+pub struct BodyStream {
+    chunks: Pin<Box<dyn Stream<Item = io::Result<Vec<u8>>> + Send>>,
+    length: Option<u64>,
+}
+
+impl BodyStream {
+    pub fn new<S>(chunks: S, length: Option<u64>) -> Self
+    where
+        S: Stream<Item = io::Result<Vec<u8>>> + Send + 'static,
+    {
+        Self { chunks: Box::pin(chunks), length }
+    }
+
+    pub fn from_reader<R>(reader: R, length: Option<u64>) -> Self
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        let chunks = stream::unfold(Box::pin(reader), |mut reader| async move {
+            let mut chunk = vec![0; STREAM_CHUNK_SIZE];
+            match reader.read(&mut chunk).await {
+                Ok(0) => None,
+                Ok(n) => {
+                    chunk.truncate(n);
+                    Some((Ok(chunk), reader))
+                }
+                Err(e) => Some((Err(e), reader)),
+            }
+        });
+
+        Self::new(chunks, length)
+    }
+
+    pub fn from_receiver(mut receiver: Receiver<Vec<u8>>) -> Self {
+        let chunks = stream::poll_fn(move |cx| receiver.poll_recv(cx).map(|chunk| chunk.map(Ok)));
+        Self::new(chunks, None)
+    }
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyStream").field("length", &self.length).finish_non_exhaustive()
+    }
 }
 
-#[derive(Debug, Clone)]
 pub struct Response {
     status_code: u16,
     status_text: String,
-    headers: HashMap<String, String>,
+    headers: HeaderMap,
     body: ResponseBody,
+    omit_body: bool,
 }
 
 impl Response {
@@ -22,83 +89,548 @@ impl Response {
         Self {
             status_code,
             status_text: status_text.to_string(),
-            headers: HashMap::new(),
+            headers: HeaderMap::new(),
             body: ResponseBody::Raw(Vec::new()),
+            omit_body: false,
+        }
+    }
+
+    // 303 sends the client to `location` with a GET, whatever the method of
+    // the request was, which is what a form submission should end in
+    pub fn redirect(location: &str) -> Self {
+        Response::new(303, "See Other").with_header("Location", location)
+    }
+
+    // The response an error stands for: rejected requests explain themselves
+    // to the client, while internal failures stay a bare 500.
+    pub fn from_error(err: &ApplicationError) -> Self {
+        let status_code = match err {
+            ApplicationError::RequestRejected { status, .. } => *status,
+            ApplicationError::HeadersTooLarge => 431,
+            ApplicationError::PayloadTooLarge => 413,
+            ApplicationError::RequestTimeout => 408,
+            ApplicationError::UnsupportedTransferEncoding(_) => 501,
+            ApplicationError::InvalidRequestFormat
+            | ApplicationError::InvalidHttpMethod(_)
+            | ApplicationError::InvalidHttpVersion(_)
+            | ApplicationError::InvalidRequestLine
+            | ApplicationError::InvalidFormat
+            | ApplicationError::InvalidHeaderFormat
+            | ApplicationError::InvalidHeader(_)
+            | ApplicationError::MissingRequiredHeaders
+            | ApplicationError::UrlParseError(_) => 400,
+            _ => 500,
+        };
+        let response = Response::new(status_code, reason_phrase(status_code));
+
+        match err {
+            ApplicationError::RequestRejected { message, .. } => {
+                let body = serde_json::json!({ "error": message });
+                response.with_raw_body(body.to_string().into_bytes(), "application/json")
+            }
+            _ => response,
         }
     }
 
+    pub fn status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
     pub fn with_status(mut self, code: u16, text: &str) -> Self {
         self.status_code = code;
         self.status_text = text.to_string();
         self
     }
 
+    // Replaces any earlier value. A header that could not be sent safely is
+    // dropped rather than failing the whole response.
     pub fn with_header(mut self, key: &str, value: &str) -> Self {
-        self.headers.insert(key.to_string(), value.to_string());
+        if let Err(e) = self.headers.insert(key, value) {
+            warn!("Dropping response header: {}", e);
+        }
         self
     }
 
+    // Adds another value next to the existing ones, as needed for Set-Cookie
+    pub fn with_appended_header(mut self, key: &str, value: &str) -> Self {
+        if let Err(e) = self.headers.append(key, value) {
+            warn!("Dropping response header: {}", e);
+        }
+        self
+    }
+
+    // Each cookie goes out in a Set-Cookie header of its own
+    pub fn with_cookie(self, cookie: &Cookie) -> Self {
+        match cookie.to_header_value() {
+            Ok(value) => self.with_appended_header("Set-Cookie", &value),
+            Err(e) => {
+                warn!("Dropping cookie: {}", e);
+                self
+            }
+        }
+    }
+
     pub fn with_text_body(mut self, text: &str) -> Self {
-        self.headers.insert("Content-Type".to_string(), "text/plain; charset=utf-8".to_string());
+        self = self.with_header("Content-Type", "text/plain; charset=utf-8");
         self.body = ResponseBody::Text(text.to_string());
         self
     }
 
     pub fn with_html_body(mut self, html: &str) -> Self {
-        self.headers.insert("Content-Type".to_string(), "text/html; charset=utf-8".to_string());
+        self = self.with_header("Content-Type", "text/html; charset=utf-8");
         self.body = ResponseBody::Text(html.to_string());
         self
     }
 
     pub fn with_json_body<T: Serialize>(mut self, data: &T) -> Result<Self, serde_json::Error> {
         let json_value = serde_json::to_value(data)?;
-        self.headers.insert("Content-Type".to_string(), "application/json".to_string());
+        self = self.with_header("Content-Type", "application/json");
         self.body = ResponseBody::Json(json_value);
         Ok(self)
     }
 
     pub fn with_raw_body(mut self, body: Vec<u8>, content_type: &str) -> Self {
-        self.headers.insert("Content-Type".to_string(), content_type.to_string());
+        self = self.with_header("Content-Type", content_type);
         self.body = ResponseBody::Raw(body);
         self
     }
 
-    fn body_bytes(&self) -> Vec<u8> {
+    pub fn with_chunked_body(self, chunks: Receiver<Vec<u8>>, content_type: &str) -> Self {
+        self.with_stream_body(BodyStream::from_receiver(chunks), content_type)
+    }
+
+    pub fn with_stream_body(mut self, body: BodyStream, content_type: &str) -> Self {
+        self = self.with_header("Content-Type", content_type);
+        self.body = ResponseBody::Stream(body);
+        self
+    }
+
+    // `None` for streamed bodies whose size is only known once they end
+    pub fn content_length(&self) -> Option<u64> {
         match &self.body {
-            ResponseBody::Text(text) => text.as_bytes().to_vec(),
+            ResponseBody::Text(text) => Some(text.len() as u64),
+            ResponseBody::Json(json) => Some(json.to_string().len() as u64),
+            ResponseBody::Raw(bytes) => Some(bytes.len() as u64),
+            ResponseBody::Stream(body) => body.length,
+        }
+    }
+
+    // Sends the headers, including the length, that the full response would
+    // have had but leaves the body out, as required for HEAD requests
+    pub fn without_body(mut self) -> Self {
+        self.omit_body = true;
+        self
+    }
+
+    pub fn with_keep_alive(self, keep_alive: bool) -> Self {
+        self.with_header("Connection", if keep_alive { "keep-alive" } else { "close" })
+    }
+
+    // Compresses the body and labels it as such. A length set for the
+    // uncompressed body no longer applies, and a streamed body ends up with
+    // no known length at all.
+    pub fn with_content_encoding(mut self, encoding: ContentEncoding) -> io::Result<Self> {
+        self.body = match self.body {
+            ResponseBody::Text(text) => ResponseBody::Raw(encode(encoding, text.as_bytes())?),
+            ResponseBody::Json(json) => ResponseBody::Raw(encode(encoding, json.to_string().as_bytes())?),
+            ResponseBody::Raw(bytes) => ResponseBody::Raw(encode(encoding, &bytes)?),
+            ResponseBody::Stream(body) => ResponseBody::Stream(encode_stream(body, encoding)),
+        };
+        self.headers.remove("Content-Length");
+        Ok(self.with_header("Content-Encoding", encoding.as_str()))
+    }
+
+    pub fn closes_connection(&self) -> bool {
+        self.header("Connection").is_some_and(|value| value.eq_ignore_ascii_case("close"))
+    }
+
+    pub async fn write_to<W: AsyncWrite + Unpin>(self, writer: &mut W, version: HttpVersion) -> io::Result<()> {
+        let Response { status_code, status_text, mut headers, body, omit_body } = self;
+
+        let body = match body {
+            ResponseBody::Text(text) => text.into_bytes(),
             ResponseBody::Json(json) => json.to_string().into_bytes(),
-            ResponseBody::Raw(bytes) => bytes.clone(),
+            ResponseBody::Raw(bytes) => bytes,
+            ResponseBody::Stream(body) => {
+                // How a stream is framed depends on what is known about it,
+                // so no handler-set framing headers may contradict that.
+                headers.remove("Content-Length");
+                headers.remove("Transfer-Encoding");
+                let head = status_line_and_headers(status_code, &status_text, &headers);
+                return write_stream(writer, head, body, version, omit_body).await;
+            }
+        };
+        let mut head = status_line_and_headers(status_code, &status_text, &headers);
+
+        // 1xx, 204 and 304 responses never carry a body, not even an empty one
+        let bodiless_status = status_code < 200 || status_code == 204 || status_code == 304;
+
+        if !headers.contains("Content-Length") && !bodiless_status {
+            head.extend_from_slice(format!("Content-Length: {}\r\n", body.len()).as_bytes());
         }
+        head.extend_from_slice(b"\r\n");
+
+        // The body goes out untouched after the head so binary payloads are
+        // never re-encoded on their way to the socket.
+        writer.write_all(&head).await?;
+        if !omit_body && !bodiless_status {
+            writer.write_all(&body).await?;
+        }
+        writer.flush().await
     }
 
-    fn body_string(&self) -> String {
-        match &self.body {
-            ResponseBody::Text(text) => text.clone(),
-            ResponseBody::Json(json) => json.to_string(),
-            ResponseBody::Raw(bytes) => String::from_utf8_lossy(bytes).to_string(),
+    // The HTTP/2 counterpart of `write_to`, for one stream of a connection.
+    // There is no status text and no connection-level framing; the body goes
+    // out as the client's flow-control window allows.
+    pub async fn write_h2(self, respond: &mut SendResponse<Bytes>) -> Result<(), h2::Error> {
+        let length = self.content_length();
+        let Response { status_code, mut headers, body, omit_body, .. } = self;
+
+        // As over HTTP/1.1, a stream's length is whatever the stream says
+        if matches!(body, ResponseBody::Stream(_)) {
+            headers.remove("Content-Length");
+        }
+
+        let mut head = http::Response::new(());
+        *head.status_mut() = http::StatusCode::from_u16(status_code).map_err(|_| h2::Error::from(Reason::INTERNAL_ERROR))?;
+
+        for (name, value) in headers.iter() {
+            if CONNECTION_SPECIFIC_HEADERS.iter().any(|header| name.eq_ignore_ascii_case(header)) {
+                continue;
+            }
+            match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_bytes(value.as_bytes())) {
+                (Ok(name), Ok(value)) => {
+                    head.headers_mut().append(name, value);
+                }
+                _ => warn!("Dropping response header '{}' that HTTP/2 cannot carry", name),
+            }
+        }
+
+        // 1xx, 204 and 304 responses never carry a body, not even an empty one
+        let bodiless_status = status_code < 200 || status_code == 204 || status_code == 304;
+
+        if let Some(length) = length
+            && !bodiless_status
+            && !headers.contains("Content-Length")
+        {
+            head.headers_mut().insert(http::header::CONTENT_LENGTH, HeaderValue::from(length));
+        }
+
+        let end_of_stream = omit_body || bodiless_status || length == Some(0);
+        let mut stream = respond.send_response(head, end_of_stream)?;
+        if end_of_stream {
+            return Ok(());
+        }
+
+        match body {
+            ResponseBody::Text(text) => send_h2_data(&mut stream, Bytes::from(text), true).await,
+            ResponseBody::Json(json) => send_h2_data(&mut stream, Bytes::from(json.to_string()), true).await,
+            ResponseBody::Raw(bytes) => send_h2_data(&mut stream, Bytes::from(bytes), true).await,
+            ResponseBody::Stream(body) => send_h2_stream(&mut stream, body).await,
         }
     }
 }
 
-impl Into<String> for Response {
-    fn into(self) -> String {
-        let mut response_str = format!("HTTP/1.1 {} {}\r\n", self.status_code, self.status_text);
+// Like requests, responses get logged: the body is summed up by its length,
+// and Set-Cookie values are masked by `HeaderMap`
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("status_code", &self.status_code)
+            .field("status_text", &self.status_text)
+            .field("headers", &self.headers)
+            .field("content_length", &self.content_length())
+            .field("omit_body", &self.omit_body)
+            .finish_non_exhaustive()
+    }
+}
+
+fn reason_phrase(status_code: u16) -> &'static str {
+    match status_code {
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Content",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "Error",
+    }
+}
 
-        for (name, value) in &self.headers {
-            response_str.push_str(&format!("{}: {}\r\n", name, value));
+fn status_line_and_headers(status_code: u16, status_text: &str, headers: &HeaderMap) -> Vec<u8> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status_code, status_text).into_bytes();
+
+    for (name, value) in headers.iter() {
+        head.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+    }
+
+    head
+}
+
+// Each chunk is written (and flushed) before the next one is pulled from the
+// stream, so a slow client throttles the producer instead of growing a buffer.
+async fn write_stream<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    mut head: Vec<u8>,
+    mut body: BodyStream,
+    version: HttpVersion,
+    omit_body: bool,
+) -> io::Result<()> {
+    // With no known length an HTTP/1.1 body is framed in chunks, while an
+    // HTTP/1.0 client can only rely on the connection closing.
+    let chunked = body.length.is_none() && version == HttpVersion::Http11;
+
+    match body.length {
+        Some(length) => head.extend_from_slice(format!("Content-Length: {}\r\n", length).as_bytes()),
+        None if chunked => head.extend_from_slice(b"Transfer-Encoding: chunked\r\n"),
+        None => {}
+    }
+    head.extend_from_slice(b"\r\n");
+    writer.write_all(&head).await?;
+
+    if omit_body {
+        return writer.flush().await;
+    }
+
+    let mut written: u64 = 0;
+
+    while let Some(chunk) = body.chunks.next().await {
+        let chunk = chunk?;
+
+        // An empty chunk would be read as the end of the body
+        if chunk.is_empty() {
+            continue;
         }
 
-        if !self.headers.contains_key("Content-Length") {
-            let body_size = match &self.body {
-                ResponseBody::Text(text) => text.len(),
-                ResponseBody::Json(json) => json.to_string().len(),
-                ResponseBody::Raw(bytes) => bytes.len(),
-            };
-            response_str.push_str(&format!("Content-Length: {}\r\n", body_size));
+        written += chunk.len() as u64;
+        if body.length.is_some_and(|length| written > length) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "response body longer than its Content-Length"));
         }
 
-        response_str.push_str("\r\n");
-        response_str.push_str(&self.body_string());
-        response_str
+        if chunked {
+            writer.write_all(format!("{:X}\r\n", chunk.len()).as_bytes()).await?;
+            writer.write_all(&chunk).await?;
+            writer.write_all(b"\r\n").await?;
+        } else {
+            writer.write_all(&chunk).await?;
+        }
+        writer.flush().await?;
+    }
+
+    if body.length.is_some_and(|length| written < length) {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "response body shorter than its Content-Length"));
+    }
+
+    if chunked {
+        writer.write_all(b"0\r\n\r\n").await?;
+    }
+    writer.flush().await
+}
+
+// Compresses each chunk as it is pulled, so the body is never held in full
+fn encode_stream(body: BodyStream, encoding: ContentEncoding) -> BodyStream {
+    let chunks = stream::unfold(Some((body.chunks, Encoder::new(encoding))), |state| async move {
+        let (mut chunks, mut encoder) = state?;
+
+        loop {
+            match chunks.next().await {
+                Some(Ok(chunk)) => match encoder.write(&chunk) {
+                    // The compressor is still collecting input
+                    Ok(encoded) if encoded.is_empty() => continue,
+                    Ok(encoded) => return Some((Ok(encoded), Some((chunks, encoder)))),
+                    Err(e) => return Some((Err(e), None)),
+                },
+                Some(Err(e)) => return Some((Err(e), None)),
+                None => return Some((encoder.finish(), None)),
+            }
+        }
+    });
+
+    BodyStream::new(chunks, None)
+}
+
+// Forwards the body chunk by chunk. A failing or mis-sized body resets the
+// stream, which tells the client the response is incomplete.
+async fn send_h2_stream(stream: &mut SendStream<Bytes>, mut body: BodyStream) -> Result<(), h2::Error> {
+    let mut written: u64 = 0;
+
+    while let Some(chunk) = body.chunks.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                warn!("Response body failed: {}", e);
+                stream.send_reset(Reason::INTERNAL_ERROR);
+                return Ok(());
+            }
+        };
+
+        written += chunk.len() as u64;
+        if body.length.is_some_and(|length| written > length) {
+            warn!("Response body longer than its Content-Length");
+            stream.send_reset(Reason::INTERNAL_ERROR);
+            return Ok(());
+        }
+
+        send_h2_data(stream, Bytes::from(chunk), false).await?;
+    }
+
+    if body.length.is_some_and(|length| written < length) {
+        warn!("Response body shorter than its Content-Length");
+        stream.send_reset(Reason::INTERNAL_ERROR);
+        return Ok(());
+    }
+
+    stream.send_data(Bytes::new(), true)
+}
+
+// Waits for window space before each piece, so a slow client throttles the
+// producer here too instead of growing a buffer
+async fn send_h2_data(stream: &mut SendStream<Bytes>, mut data: Bytes, end_of_stream: bool) -> Result<(), h2::Error> {
+    if data.is_empty() {
+        return if end_of_stream { stream.send_data(data, true) } else { Ok(()) };
+    }
+
+    while !data.is_empty() {
+        stream.reserve_capacity(data.len());
+
+        let capacity = match poll_fn(|cx| stream.poll_capacity(cx)).await {
+            Some(capacity) => capacity?,
+            // The client reset the stream or the connection is gone
+            None => return Err(Reason::CANCEL.into()),
+        };
+
+        let chunk = data.split_to(capacity.min(data.len()));
+        stream.send_data(chunk, end_of_stream && data.is_empty())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn streamed(chunks: &[&str], length: Option<u64>) -> Response {
+        let chunks: Vec<io::Result<Vec<u8>>> = chunks.iter().map(|chunk| Ok(chunk.as_bytes().to_vec())).collect();
+        Response::new(200, "OK").with_stream_body(BodyStream::new(stream::iter(chunks), length), "text/plain")
     }
-}
\ No newline at end of file
+
+    async fn written(response: Response, version: HttpVersion) -> io::Result<String> {
+        let mut written = Vec::new();
+        response.write_to(&mut written, version).await?;
+        Ok(String::from_utf8(written).unwrap())
+    }
+
+    #[tokio::test]
+    async fn streams_unknown_lengths_in_chunks() {
+        let written = written(streamed(&["Hello, ", "", "chunked world"], None), HttpVersion::Http11).await.unwrap();
+
+        assert!(written.contains("\r\nTransfer-Encoding: chunked\r\n"), "{}", written);
+        assert!(!written.contains("Content-Length"), "{}", written);
+        // The empty chunk is skipped rather than ending the body early
+        assert!(written.ends_with("\r\n\r\n7\r\nHello, \r\nD\r\nchunked world\r\n0\r\n\r\n"), "{}", written);
+    }
+
+    #[tokio::test]
+    async fn streams_unknown_lengths_as_is_to_http10() {
+        let written = written(streamed(&["Hello, ", "old world"], None), HttpVersion::Http10).await.unwrap();
+
+        assert!(!written.contains("Transfer-Encoding") && !written.contains("Content-Length"), "{}", written);
+        assert!(written.ends_with("\r\n\r\nHello, old world"), "{}", written);
+    }
+
+    #[tokio::test]
+    async fn streams_known_lengths_as_is() {
+        let response = streamed(&["Hello, ", "world"], Some(12))
+            .with_header("Content-Length", "999")
+            .with_header("Transfer-Encoding", "chunked");
+
+        let written = written(response, HttpVersion::Http11).await.unwrap();
+        assert!(written.contains("\r\nContent-Length: 12\r\n"), "{}", written);
+        assert!(!written.contains("999") && !written.contains("Transfer-Encoding"), "{}", written);
+        assert!(written.ends_with("\r\n\r\nHello, world"), "{}", written);
+    }
+
+    #[tokio::test]
+    async fn fails_streams_that_break_their_length() {
+        let longer = written(streamed(&["Hello, ", "world"], Some(10)), HttpVersion::Http11).await.unwrap_err();
+        assert_eq!(longer.kind(), io::ErrorKind::InvalidData);
+
+        let shorter = written(streamed(&["Hello, ", "world"], Some(20)), HttpVersion::Http11).await.unwrap_err();
+        assert_eq!(shorter.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn stops_writing_once_a_stream_overruns_its_length() {
+        let mut written = Vec::new();
+        let response = streamed(&["12345", "67890", "overflow"], Some(10));
+
+        assert!(response.write_to(&mut written, HttpVersion::Http11).await.is_err());
+        assert!(written.ends_with(b"\r\n\r\n1234567890"), "{}", String::from_utf8_lossy(&written));
+    }
+
+    #[tokio::test]
+    async fn writes_binary_bodies_byte_for_byte() {
+        let body: Vec<u8> = (0..=255).chain([b'\r', b'\n', b'\r', b'\n', 0xFF, 0]).collect();
+        let response = Response::new(200, "OK").with_raw_body(body.clone(), "application/octet-stream");
+
+        let mut written = Vec::new();
+        response.write_to(&mut written, HttpVersion::Http11).await.unwrap();
+
+        let head_end = written.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8(written[..head_end].to_vec()).unwrap();
+        assert!(head.contains("\r\nContent-Length: 262\r\n"), "{}", head);
+        assert_eq!(&written[head_end..], &body[..]);
+    }
+
+    #[tokio::test]
+    async fn leaves_the_body_out_of_304s() {
+        let response = Response::new(304, "Not Modified").with_header("ETag", "\"v1\"").with_text_body("stale");
+
+        let written = written(response, HttpVersion::Http11).await.unwrap();
+        assert!(written.starts_with("HTTP/1.1 304 Not Modified\r\n"), "{}", written);
+        assert!(!written.contains("Content-Length"), "{}", written);
+        assert!(written.ends_with("\r\n\r\n"), "{}", written);
+    }
+
+    #[tokio::test]
+    async fn leaves_the_body_out_of_304s_over_http2() {
+        let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+
+        tokio::spawn(async move {
+            let mut connection = h2::server::handshake(server_end).await.unwrap();
+            let (_, mut respond) = connection.accept().await.unwrap().unwrap();
+            let response = Response::new(304, "Not Modified").with_text_body("stale");
+            response.write_h2(&mut respond).await.unwrap();
+            // Keeps the connection going until the client has the response
+            while connection.accept().await.is_some() {}
+        });
+
+        let (send_request, connection) = h2::client::handshake(client_end).await.unwrap();
+        tokio::spawn(connection);
+        let mut send_request = send_request.ready().await.unwrap();
+        let (response, _) = send_request.send_request(http::Request::get("http://localhost/").body(()).unwrap(), true).unwrap();
+
+        let response = response.await.unwrap();
+        assert_eq!(response.status(), 304);
+        assert!(response.headers().get(http::header::CONTENT_LENGTH).is_none());
+        assert!(response.body().is_end_stream());
+    }
+}
@@ -1,33 +1,406 @@
 use std::sync::Arc;
+use tracing::error;
+use crate::server::methods::HttpMethod;
+use crate::server::middleware::{Layered, Middleware, Next};
+use crate::server::pattern::PathPattern;
 use crate::server::request::Request;
 use crate::server::response::Response;
 use crate::server::route::Route;
+use crate::server::tree::RouteTree;
 use crate::utils::error::ApplicationError;
 
 #[derive(Default)]
 pub struct Router {
-    routes: Vec<Arc<dyn Route>>,
+    routes: RouteTree<Arc<dyn Route>>,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+// Routes added through a group share a path prefix and run the group's
+// middleware after the router-wide middleware and before the route itself.
+pub struct RouteGroup<'a> {
+    router: &'a mut Router,
+    prefix: String,
+    middleware: Vec<Arc<dyn Middleware>>,
 }
 
 impl Router {
-    pub fn new(routes: Vec<Arc<dyn Route>>) -> Self {
-        Self {
-            routes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_route(&mut self, method: HttpMethod, pattern: &str, route: Arc<dyn Route>) -> Result<(), ApplicationError> {
+        let pattern = PathPattern::parse(pattern)?;
+        self.routes.insert(method, &pattern, route)
+    }
+
+    // Router-wide middleware sees every request, including the ones that end
+    // in 404 or 405. The first layer added is the outermost one.
+    pub fn layer(&mut self, middleware: Arc<dyn Middleware>) {
+        self.middleware.push(middleware);
+    }
+
+    pub fn group(&mut self) -> RouteGroup<'_> {
+        RouteGroup {
+            router: self,
+            prefix: String::new(),
+            middleware: Vec::new(),
         }
     }
 
-    pub fn add_route(&mut self, route: Arc<dyn Route>) {
-        self.routes.push(route);
+    // Mounts every route of `router` below `prefix`. The nested router's own
+    // middleware keeps applying to its routes only, inside this router's.
+    pub fn nest(&mut self, prefix: &str, router: Router) -> Result<(), ApplicationError> {
+        let Router { routes, middleware } = router;
+
+        for (method, pattern, route) in routes.into_routes() {
+            let route: Arc<dyn Route> = if middleware.is_empty() {
+                route
+            } else {
+                Arc::new(Layered::new(route, middleware.clone()))
+            };
+            self.add_route(method, &join_paths(prefix, &pattern), route)?;
+        }
+
+        Ok(())
     }
 
     pub async fn route(&self, req: Request) -> Result<Response, ApplicationError> {
-        for route in &self.routes {
-            if route.path_matches(&req.path()) && route.method_matches(&req.method()) {
-                return route.handle(req).await;
+        Next::new(&self.middleware, &Dispatch { router: self }).run(req).await
+    }
+
+    async fn dispatch(&self, req: Request) -> Result<Response, ApplicationError> {
+        let method = req.method().clone();
+
+        if !self.implements(&method) {
+            return Ok(Response::new(501, "Not Implemented"));
+        }
+
+        if let Some(found) = self.routes.find(&method, req.path()) {
+            let response = found.value.handle(req.with_params(found.params)).await?;
+            return Ok(without_body_for_head(method, response));
+        }
+
+        // Any GET route also answers HEAD, minus the body
+        if method == HttpMethod::HEAD
+            && let Some(found) = self.routes.find(&HttpMethod::GET, req.path())
+        {
+            let response = found.value.handle(req.with_params(found.params)).await?;
+            return Ok(response.without_body());
+        }
+
+        // "OPTIONS *" asks about the server as a whole rather than a resource
+        if method == HttpMethod::OPTIONS && req.path() == "*" {
+            return Ok(Response::new(204, "No Content").with_header("Allow", &allow_header(self.routes.methods())));
+        }
+
+        let allowed = self.routes.allowed_methods(req.path());
+        if allowed.is_empty() {
+            return Ok(Response::new(404, "Not Found"));
+        }
+
+        let allow = allow_header(&allowed);
+
+        if method == HttpMethod::OPTIONS {
+            return Ok(Response::new(204, "No Content").with_header("Allow", &allow));
+        }
+
+        Ok(Response::new(405, "Method Not Allowed").with_header("Allow", &allow))
+    }
+
+    // The methods every resource could in principle support are answered with
+    // 404 or 405; anything else is only understood once a route uses it.
+    fn implements(&self, method: &HttpMethod) -> bool {
+        !matches!(method, HttpMethod::CONNECT | HttpMethod::TRACE | HttpMethod::Extension(_))
+            || self.routes.methods().contains(method)
+    }
+}
+
+impl RouteGroup<'_> {
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = join_paths(&self.prefix, prefix);
+        self
+    }
+
+    pub fn layer(mut self, middleware: Arc<dyn Middleware>) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    pub fn add_route(&mut self, method: HttpMethod, pattern: &str, route: Arc<dyn Route>) -> Result<(), ApplicationError> {
+        let pattern = join_paths(&self.prefix, pattern);
+        let route: Arc<dyn Route> = if self.middleware.is_empty() {
+            route
+        } else {
+            Arc::new(Layered::new(route, self.middleware.clone()))
+        };
+        self.router.add_route(method, &pattern, route)
+    }
+}
+
+// Route lookup itself, as the innermost step of the router-wide chain
+struct Dispatch<'a> {
+    router: &'a Router,
+}
+
+#[async_trait::async_trait]
+impl Route for Dispatch<'_> {
+    // Errors become responses here, inside the chain, so middleware such as
+    // the access log sees the status the client actually gets.
+    async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+        match self.router.dispatch(req).await {
+            Ok(response) => Ok(response),
+            Err(e) => {
+                error!("Error handling request: {}", e);
+                Ok(Response::from_error(&e))
             }
         }
+    }
+}
+
+fn without_body_for_head(method: HttpMethod, response: Response) -> Response {
+    if method == HttpMethod::HEAD {
+        response.without_body()
+    } else {
+        response
+    }
+}
+
+// "/api" + "/symbols" is "/api/symbols", and a group's own root "/" maps
+// onto the prefix itself
+fn join_paths(prefix: &str, path: &str) -> String {
+    let prefix = prefix.trim_end_matches('/');
+
+    match path {
+        "" | "/" if !prefix.is_empty() => prefix.to_string(),
+        _ if path.starts_with('/') => format!("{}{}", prefix, path),
+        _ => format!("{}/{}", prefix, path),
+    }
+}
+
+// Lists the registered methods plus the ones the router answers on its own
+fn allow_header(methods: &[HttpMethod]) -> String {
+    let mut allowed = methods.to_vec();
+
+    if allowed.contains(&HttpMethod::GET) {
+        allowed.push(HttpMethod::HEAD);
+    }
+    allowed.push(HttpMethod::OPTIONS);
+
+    allowed.sort();
+    allowed.dedup();
+
+    allowed.iter().map(HttpMethod::as_str).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::version::HttpVersion;
+
+    // Answers with its own name, so tests can tell which route ran
+    struct Named(&'static str);
+
+    #[async_trait::async_trait]
+    impl Route for Named {
+        async fn handle(&self, _req: Request) -> Result<Response, ApplicationError> {
+            Ok(Response::new(200, "OK").with_text_body(self.0))
+        }
+    }
 
-        let response = Response::new(404, "Not Found");
-        Ok(response)
+    fn named(name: &'static str) -> Arc<dyn Route> {
+        Arc::new(Named(name))
     }
-}
\ No newline at end of file
+
+    // What the client receives, status line to body
+    async fn send(router: &Router, method: &str, path: &str) -> String {
+        let raw = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n\r\n", method, path);
+        let response = router.route(Request::try_from(raw.as_bytes()).unwrap()).await.unwrap();
+
+        let mut written = Vec::new();
+        response.write_to(&mut written, HttpVersion::Http11).await.unwrap();
+        String::from_utf8(written).unwrap()
+    }
+
+    fn quotes_router() -> Router {
+        let mut router = Router::new();
+        router.add_route(HttpMethod::GET, "/quotes/{ticker}", named("show")).unwrap();
+        router.add_route(HttpMethod::DELETE, "/quotes/{ticker}", named("delete")).unwrap();
+        router.add_route(HttpMethod::POST, "/quotes", named("create")).unwrap();
+        router
+    }
+
+    #[tokio::test]
+    async fn answers_405_with_the_allowed_methods() {
+        let router = quotes_router();
+
+        let written = send(&router, "PUT", "/quotes/AAPL").await;
+        assert!(written.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{}", written);
+        assert!(written.contains("\r\nAllow: GET, HEAD, DELETE, OPTIONS\r\n"), "{}", written);
+
+        let written = send(&router, "GET", "/quotes").await;
+        assert!(written.starts_with("HTTP/1.1 405 "), "{}", written);
+        assert!(written.contains("\r\nAllow: POST, OPTIONS\r\n"), "{}", written);
+
+        let written = send(&router, "PUT", "/nothing/here").await;
+        assert!(written.starts_with("HTTP/1.1 404 "), "{}", written);
+        assert!(!written.contains("Allow:"), "{}", written);
+    }
+
+    #[tokio::test]
+    async fn answers_options_with_204_and_no_body() {
+        let router = quotes_router();
+
+        let written = send(&router, "OPTIONS", "/quotes/AAPL").await;
+        assert!(written.starts_with("HTTP/1.1 204 No Content\r\n"), "{}", written);
+        assert!(written.contains("\r\nAllow: GET, HEAD, DELETE, OPTIONS\r\n"), "{}", written);
+        assert!(!written.contains("Content-Length"), "{}", written);
+        assert!(written.ends_with("\r\n\r\n"), "{}", written);
+
+        let written = send(&router, "OPTIONS", "*").await;
+        assert!(written.starts_with("HTTP/1.1 204 "), "{}", written);
+        assert!(written.contains("\r\nAllow: GET, HEAD, POST, DELETE, OPTIONS\r\n"), "{}", written);
+    }
+
+    #[tokio::test]
+    async fn answers_head_from_the_get_route_without_the_body() {
+        let router = quotes_router();
+
+        let get = send(&router, "GET", "/quotes/AAPL").await;
+        assert!(get.ends_with("\r\n\r\nshow"), "{}", get);
+
+        let head = send(&router, "HEAD", "/quotes/AAPL").await;
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(head.contains("\r\nContent-Length: 4\r\n"), "{}", head);
+        assert!(head.ends_with("\r\n\r\n"), "{}", head);
+        assert_eq!(head, get.trim_end_matches("show"));
+
+        // A HEAD route of its own takes precedence
+        let mut router = quotes_router();
+        router.add_route(HttpMethod::HEAD, "/quotes/{ticker}", named("headers")).unwrap();
+        let head = send(&router, "HEAD", "/quotes/AAPL").await;
+        assert!(head.contains("\r\nContent-Length: 7\r\n"), "{}", head);
+        assert!(head.ends_with("\r\n\r\n"), "{}", head);
+    }
+
+    #[tokio::test]
+    async fn answers_501_to_methods_no_route_uses() {
+        let router = quotes_router();
+
+        for method in ["PROPFIND", "get", "TRACE", "CONNECT"] {
+            let written = send(&router, method, "/quotes/AAPL").await;
+            assert!(written.starts_with("HTTP/1.1 501 Not Implemented\r\n"), "{}: {}", method, written);
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_registered_extension_methods() {
+        let mut router = quotes_router();
+        let propfind = HttpMethod::try_from("PROPFIND").unwrap();
+        assert_eq!(propfind, HttpMethod::Extension("PROPFIND".to_string()));
+        router.add_route(propfind, "/quotes/{ticker}", named("properties")).unwrap();
+
+        let written = send(&router, "PROPFIND", "/quotes/AAPL").await;
+        assert!(written.ends_with("\r\n\r\nproperties"), "{}", written);
+
+        // Once known, the method is answered like any other
+        let written = send(&router, "PROPFIND", "/quotes").await;
+        assert!(written.starts_with("HTTP/1.1 405 "), "{}", written);
+        let written = send(&router, "OPTIONS", "/quotes/AAPL").await;
+        assert!(written.contains("\r\nAllow: GET, HEAD, DELETE, OPTIONS, PROPFIND\r\n"), "{}", written);
+
+        // Other extension methods still are not
+        let written = send(&router, "PROPPATCH", "/quotes/AAPL").await;
+        assert!(written.starts_with("HTTP/1.1 501 "), "{}", written);
+    }
+
+    // Marks every response that went through it
+    struct Tag(&'static str);
+
+    #[async_trait::async_trait]
+    impl Middleware for Tag {
+        async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, ApplicationError> {
+            Ok(next.run(req).await?.with_appended_header("X-Layer", self.0))
+        }
+    }
+
+    #[test]
+    fn joins_prefixes_and_paths_with_one_slash() {
+        let cases = [
+            ("", "/quotes", "/quotes"),
+            ("/api", "/quotes", "/api/quotes"),
+            ("/api/", "/quotes", "/api/quotes"),
+            ("/api", "quotes", "/api/quotes"),
+            ("/api/", "quotes/", "/api/quotes/"),
+            ("/api", "/", "/api"),
+            ("/api", "", "/api"),
+            ("/", "/quotes", "/quotes"),
+            ("/", "/", "/"),
+            ("", "", "/"),
+        ];
+
+        for (prefix, path, joined) in cases {
+            assert_eq!(join_paths(prefix, path), joined, "{:?} + {:?}", prefix, path);
+        }
+    }
+
+    #[tokio::test]
+    async fn groups_add_their_prefix_and_layers_to_their_own_routes() {
+        let mut router = Router::new();
+        router.add_route(HttpMethod::GET, "/", named("home")).unwrap();
+
+        let mut api = router.group().prefix("/api/").prefix("v1").layer(Arc::new(Tag("api")));
+        api.add_route(HttpMethod::GET, "/", named("index")).unwrap();
+        api.add_route(HttpMethod::GET, "quotes", named("quotes")).unwrap();
+
+        let mut admin = router.group().prefix("/").layer(Arc::new(Tag("admin")));
+        admin.add_route(HttpMethod::GET, "/admin", named("admin")).unwrap();
+
+        let written = send(&router, "GET", "/api/v1").await;
+        assert!(written.contains("\r\nX-Layer: api\r\n") && written.ends_with("index"), "{}", written);
+        let written = send(&router, "GET", "/api/v1/quotes").await;
+        assert!(written.contains("\r\nX-Layer: api\r\n") && written.ends_with("quotes"), "{}", written);
+
+        let written = send(&router, "GET", "/admin").await;
+        assert!(written.contains("\r\nX-Layer: admin\r\n") && !written.contains("X-Layer: api"), "{}", written);
+
+        let written = send(&router, "GET", "/").await;
+        assert!(!written.contains("X-Layer"), "{}", written);
+        assert!(send(&router, "GET", "/quotes").await.starts_with("HTTP/1.1 404 "));
+    }
+
+    #[tokio::test]
+    async fn nested_routers_keep_their_layers_inside_the_parents() {
+        let mut quotes = Router::new();
+        quotes.layer(Arc::new(Tag("nested")));
+        quotes.add_route(HttpMethod::GET, "/", named("list")).unwrap();
+        quotes.add_route(HttpMethod::GET, "/{ticker}", named("show")).unwrap();
+
+        let mut router = Router::new();
+        router.layer(Arc::new(Tag("root")));
+        router.add_route(HttpMethod::GET, "/health", named("health")).unwrap();
+        router.nest("/quotes/", quotes).unwrap();
+
+        let written = send(&router, "GET", "/quotes").await;
+        assert!(written.ends_with("list"), "{}", written);
+        let written = send(&router, "GET", "/quotes/AAPL").await;
+        assert!(written.contains("\r\nX-Layer: nested\r\nX-Layer: root\r\n"), "{}", written);
+
+        let written = send(&router, "GET", "/health").await;
+        assert!(written.contains("\r\nX-Layer: root\r\n") && !written.contains("nested"), "{}", written);
+    }
+
+    #[test]
+    fn nesting_reports_conflicts() {
+        let mut router = Router::new();
+        router.add_route(HttpMethod::GET, "/api/quotes/{id}", named("existing")).unwrap();
+
+        let mut nested = Router::new();
+        nested.add_route(HttpMethod::GET, "/quotes/{ticker}", named("nested")).unwrap();
+        assert!(matches!(router.nest("/api", nested), Err(ApplicationError::RouteConflict(_))));
+
+        let mut router = Router::new();
+        router.add_route(HttpMethod::GET, "/api", named("existing")).unwrap();
+        let mut group = router.group().prefix("/api");
+        assert!(matches!(group.add_route(HttpMethod::GET, "/", named("group")), Err(ApplicationError::RouteConflict(_))));
+    }
+}
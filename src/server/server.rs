@@ -1,64 +1,400 @@
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use std::error::Error;
-use std::convert::TryFrom;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tracing::info;
+use bytes::Bytes;
+use h2::RecvStream;
+use h2::server::SendResponse;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio::time;
+use tokio_rustls::TlsAcceptor;
+use std::error::Error;
+use tracing::{debug, error, info};
 
-use crate::server::request::Request;
+use crate::server::config::ServerConfig;
+use crate::server::http2::{self, sniff_preface, Rewind};
+use crate::server::reader::RequestReader;
+use crate::server::response::Response;
 use crate::server::router::Router;
-use crate::server::route::Route;
-use crate::routes::root::Root;
-use crate::routes::detail::Detail;
-use crate::routes::static_files::StaticFiles;
-use crate::services::database::Database;
+use crate::server::version::HttpVersion;
+use crate::utils::error::ApplicationError;
 
 pub struct HttpServer {
     router: Router,
+    config: ServerConfig,
 }
 
 impl HttpServer {
-    pub fn new(database: Arc<Database>) -> Self {
-        let mut routes: Vec<Arc<dyn Route>> = Vec::new();
+    pub fn new(router: Router, config: ServerConfig) -> Self {
+        Self {
+            router,
+            config,
+        }
+    }
 
-        let root = Arc::new(Root::new(Arc::clone(&database)));
-        routes.push(root);
+    // Accepts connections until the task is dropped. With an acceptor every
+    // connection has to complete a TLS handshake first, which also settles
+    // the protocol through ALPN; plain connections speak HTTP/2 only if they
+    // open with its preface (prior-knowledge h2c).
+    pub async fn serve(self: Arc<Self>, listener: TcpListener, acceptor: Option<TlsAcceptor>) {
+        loop {
+            let (stream, peer_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Error accepting connection: {}", e);
+                    continue;
+                }
+            };
 
-        let detail = Arc::new(Detail::new(Arc::clone(&database)));
-        routes.push(detail);
+            let server = Arc::clone(&self);
+            let acceptor = acceptor.clone();
 
-        let static_files = Arc::new(StaticFiles::new("static".to_string()));
-        routes.push(static_files);
+            tokio::spawn(async move {
+                let result = match acceptor {
+                    Some(acceptor) => {
+                        // A client that never finishes the handshake would
+                        // otherwise hold the connection open indefinitely
+                        match time::timeout(server.config.read_timeout, acceptor.accept(stream)).await {
+                            Ok(Ok(stream)) if stream.get_ref().1.alpn_protocol() == Some(b"h2") => {
+                                server.handle_h2_connection(stream, peer_addr).await
+                            }
+                            Ok(Ok(stream)) => server.handle_connection(stream, peer_addr).await,
+                            Ok(Err(e)) => {
+                                debug!("TLS handshake with {} failed: {}", peer_addr, e);
+                                return;
+                            }
+                            Err(_) => {
+                                debug!("TLS handshake with {} timed out", peer_addr);
+                                return;
+                            }
+                        }
+                    }
+                    None => server.handle_plain_connection(stream, peer_addr).await,
+                };
 
-        let router = Router::new(routes);
+                if let Err(e) = result {
+                    error!("Error handling connection: {}", e);
+                }
+            });
+        }
+    }
 
-        Self {
-            router,
+    async fn handle_plain_connection(self: Arc<Self>, mut stream: TcpStream, peer_addr: SocketAddr) -> Result<(), Box<dyn Error>> {
+        if !self.config.http2.enabled {
+            return self.handle_connection(stream, peer_addr).await;
+        }
+
+        match sniff_preface(&mut stream, self.config.keep_alive_timeout).await? {
+            Some((true, preface)) => self.handle_h2_connection(Rewind::new(preface, stream), peer_addr).await,
+            Some((false, prefix)) => self.handle_connection(Rewind::new(prefix, stream), peer_addr).await,
+            None => Ok(()),
+        }
+    }
+
+    // Serves the streams of an HTTP/2 connection concurrently, each through
+    // the same router as an HTTP/1 request. An idle connection is closed
+    // gracefully after the keep-alive timeout.
+    pub async fn handle_h2_connection<S>(self: Arc<Self>, stream: S, peer_addr: SocketAddr) -> Result<(), Box<dyn Error>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let settings = &self.config.http2;
+        let handshake = h2::server::Builder::new()
+            .max_concurrent_streams(settings.max_concurrent_streams)
+            .initial_window_size(settings.initial_stream_window_size)
+            .initial_connection_window_size(settings.initial_connection_window_size)
+            .max_frame_size(settings.max_frame_size)
+            .max_header_list_size(u32::try_from(self.config.max_header_size).unwrap_or(u32::MAX))
+            .handshake::<_, Bytes>(stream);
+
+        let mut connection = match time::timeout(self.config.read_timeout, handshake).await {
+            Ok(connection) => connection?,
+            Err(_) => return Ok(()),
+        };
+
+        let mut streams = JoinSet::new();
+        let mut closing = false;
+
+        loop {
+            tokio::select! {
+                accepted = connection.accept() => match accepted {
+                    Some(Ok((request, respond))) => {
+                        let server = Arc::clone(&self);
+                        streams.spawn(async move { server.handle_h2_stream(request, respond, peer_addr).await });
+                    }
+                    Some(Err(e)) => return Err(Box::new(e)),
+                    None => return Ok(()),
+                },
+
+                Some(_) = streams.join_next(), if !streams.is_empty() => {}
+
+                _ = time::sleep(self.config.keep_alive_timeout), if streams.is_empty() && !closing => {
+                    // Lets the client finish what it already sent, then ends
+                    // the connection
+                    connection.graceful_shutdown();
+                    closing = true;
+                }
+
+                // The shutdown waits for the client to acknowledge it, which
+                // one that has gone away never does
+                _ = time::sleep(self.config.read_timeout), if closing => return Ok(()),
+            }
+        }
+    }
+
+    async fn handle_h2_stream(&self, request: http::Request<RecvStream>, mut respond: SendResponse<Bytes>, peer_addr: SocketAddr) {
+        let response = match http2::read_request(request, &self.config).await {
+            Ok(request) => {
+                info!("Parsed request: \n\n{:?}", request);
+
+                match self.router.route(request.with_peer_addr(peer_addr)).await {
+                    Ok(response) => response,
+                    Err(e) => {
+                        error!("Error handling request: {}", e);
+                        Response::from_error(&e)
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Error reading request: {}", e);
+                Response::from_error(&e)
+            }
+        };
+
+        info!("Response: \n\n{:?}", response);
+
+        if let Err(e) = response.write_h2(&mut respond).await {
+            debug!("Error writing HTTP/2 response: {}", e);
+        }
+    }
+
+    // Works on any byte stream, so a plain TCP connection and a TLS session
+    // on top of one are served the same way
+    pub async fn handle_connection<S>(&self, stream: S, peer_addr: SocketAddr) -> Result<(), Box<dyn Error>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (read_half, mut write_half) = tokio::io::split(stream);
+        let mut reader = RequestReader::new(read_half, &self.config);
+
+        loop {
+            let request = match reader.read_request().await {
+                Ok(Some(request)) => request.with_peer_addr(peer_addr),
+                Ok(None) => return Ok(()),
+                Err(ApplicationError::IoError(e)) => return Err(Box::new(e)),
+                Err(e) => {
+                    // Whatever is left of a rejected request is still in flight, so
+                    // the connection cannot be reused after answering it.
+                    error!("Error reading request: {}", e);
+                    let response = Response::from_error(&e).with_keep_alive(false);
+                    response.write_to(&mut write_half, HttpVersion::Http11).await?;
+                    write_half.shutdown().await?;
+                    return Ok(());
+                }
+            };
+
+            info!("Parsed request: \n\n{:?}", request);
+
+            let version = *request.version();
+            let keep_alive = request.keep_alive();
+
+            let response = match self.router.route(request).await {
+                Ok(response) => response,
+                Err(e) => {
+                    error!("Error handling request: {}", e);
+                    Response::from_error(&e)
+                }
+            };
+
+            // Without chunked framing an HTTP/1.0 client can only tell where a
+            // body of unknown length ends by the connection closing.
+            let keep_alive = keep_alive
+                && !response.closes_connection()
+                && (version == HttpVersion::Http11 || response.content_length().is_some());
+            let response = response.with_keep_alive(keep_alive);
+
+            info!("Response: \n\n{:?}", response);
+
+            response.write_to(&mut write_half, version).await?;
+
+            // Over TLS this also sends close_notify, so the client can tell
+            // the end of the body from a truncated connection
+            if !keep_alive {
+                write_half.shutdown().await?;
+                return Ok(());
+            }
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use futures_util::stream;
+    use tokio::io::{duplex, AsyncReadExt, DuplexStream};
+    use tokio::task::JoinHandle;
+    use super::*;
+    use crate::server::methods::HttpMethod;
+    use crate::server::request::Request;
+    use crate::server::response::BodyStream;
+    use crate::server::route::Route;
 
-    pub async fn handle_connection(&self, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0; 4096];
+    // Echoes the path, with a length known up front
+    struct Echo;
 
-        let n = stream.read(&mut buffer).await?;
-        if n == 0 {
-            return Ok(());
+    #[async_trait::async_trait]
+    impl Route for Echo {
+        async fn handle(&self, req: Request) -> Result<Response, ApplicationError> {
+            Ok(Response::new(200, "OK").with_text_body(req.path()))
         }
+    }
+
+    // A body whose length is only known once it ends
+    struct Streamed;
 
-        let request_string = String::from_utf8_lossy(&buffer[0..n]).to_string();
-        let request = Request::try_from(request_string.as_str())?;
+    #[async_trait::async_trait]
+    impl Route for Streamed {
+        async fn handle(&self, _req: Request) -> Result<Response, ApplicationError> {
+            let chunks = stream::iter([Ok(b"part one, ".to_vec()), Ok(b"part two".to_vec())]);
+            Ok(Response::new(200, "OK").with_stream_body(BodyStream::new(chunks, None), "text/plain"))
+        }
+    }
 
-        info!("Parsed request: \n\n{:?}", request);
+    // The client end of a connection served by `handle_connection`
+    struct Client {
+        stream: DuplexStream,
+        buffer: Vec<u8>,
+        server: JoinHandle<Result<(), String>>,
+    }
 
-        let response = self.router.route(request).await?;
-        let response_str: String = response.into();
+    impl Client {
+        fn connect(keep_alive_timeout: Duration) -> Self {
+            let mut router = Router::new();
+            router.add_route(HttpMethod::GET, "/stream", Arc::new(Streamed)).unwrap();
+            router.add_route(HttpMethod::GET, "/*path", Arc::new(Echo)).unwrap();
+            let config = ServerConfig { keep_alive_timeout, ..ServerConfig::default() };
+            let server = HttpServer::new(router, config);
 
-        info!("Response: \n\n{:?}", response_str);
+            let (stream, server_end) = duplex(64 * 1024);
+            let peer_addr = SocketAddr::from(([127, 0, 0, 1], 40000));
+            let server = tokio::spawn(async move {
+                server.handle_connection(server_end, peer_addr).await.map_err(|e| e.to_string())
+            });
 
-        stream.write_all(response_str.as_bytes()).await?;
-        stream.flush().await?;
+            Self { stream, buffer: Vec::new(), server }
+        }
 
-        Ok(())
+        async fn send(&mut self, raw: &str) {
+            self.stream.write_all(raw.as_bytes()).await.unwrap();
+        }
 
+        // The next response framed by Content-Length, head and body
+        async fn response(&mut self) -> String {
+            loop {
+                if let Some(head_end) = self.buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+                    let head = String::from_utf8_lossy(&self.buffer[..head_end]).to_string();
+                    let length: usize = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .expect("response without a Content-Length")
+                        .parse()
+                        .unwrap();
+
+                    let end = head_end + 4 + length;
+                    if self.buffer.len() >= end {
+                        let response = self.buffer.drain(..end).collect();
+                        return String::from_utf8(response).unwrap();
+                    }
+                }
+
+                let mut chunk = [0; 1024];
+                let n = self.stream.read(&mut chunk).await.unwrap();
+                assert!(n > 0, "connection closed after {:?}", String::from_utf8_lossy(&self.buffer));
+                self.buffer.extend_from_slice(&chunk[..n]);
+            }
+        }
+
+        // Everything up to the server closing the connection, which it has
+        // to do well before the test would hang
+        async fn rest(mut self) -> String {
+            let mut rest = Vec::new();
+            time::timeout(Duration::from_secs(5), self.stream.read_to_end(&mut rest)).await.unwrap().unwrap();
+            self.buffer.extend_from_slice(&rest);
+
+            self.server.await.unwrap().unwrap();
+            String::from_utf8(self.buffer).unwrap()
+        }
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn reuses_keep_alive_connections() {
+        let mut client = Client::connect(Duration::from_secs(5));
+
+        for path in ["/first", "/second", "/third"] {
+            client.send(&format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path)).await;
+            let response = client.response().await;
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+            assert!(response.contains("\r\nConnection: keep-alive\r\n"), "{}", response);
+            assert!(response.ends_with(path), "{}", response);
+        }
+
+        client.send("GET /last HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        let rest = client.rest().await;
+        assert!(rest.contains("\r\nConnection: close\r\n") && rest.ends_with("/last"), "{}", rest);
+    }
+
+    #[tokio::test]
+    async fn answers_pipelined_requests_in_order() {
+        let mut client = Client::connect(Duration::from_secs(5));
+
+        client
+            .send(concat!(
+                "GET /one HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "GET /two HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "GET /three HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            ))
+            .await;
+
+        assert!(client.response().await.ends_with("\r\n\r\n/one"));
+        assert!(client.response().await.ends_with("\r\n\r\n/two"));
+        assert!(client.rest().await.ends_with("\r\n\r\n/three"));
+    }
+
+    #[tokio::test]
+    async fn closes_connections_left_idle() {
+        let keep_alive_timeout = Duration::from_millis(100);
+        let mut client = Client::connect(keep_alive_timeout);
+
+        client.send("GET /once HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(client.response().await.ends_with("/once"));
+
+        let idle_since = Instant::now();
+        assert_eq!(client.rest().await, "");
+        assert!(idle_since.elapsed() >= keep_alive_timeout);
+    }
+
+    #[tokio::test]
+    async fn closes_http10_connections_unless_asked_not_to() {
+        let mut client = Client::connect(Duration::from_secs(5));
+        client.send("GET /old HTTP/1.0\r\n\r\n").await;
+        let rest = client.rest().await;
+        assert!(rest.contains("\r\nConnection: close\r\n") && rest.ends_with("/old"), "{}", rest);
+
+        let mut client = Client::connect(Duration::from_secs(5));
+        client.send("GET /old HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").await;
+        let response = client.response().await;
+        assert!(response.contains("\r\nConnection: keep-alive\r\n"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn closes_http10_connections_to_end_a_body_of_unknown_length() {
+        let mut client = Client::connect(Duration::from_secs(5));
+
+        client.send("GET /stream HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").await;
+        let rest = client.rest().await;
+
+        assert!(rest.contains("\r\nConnection: close\r\n"), "{}", rest);
+        assert!(!rest.contains("Transfer-Encoding") && !rest.contains("Content-Length"), "{}", rest);
+        assert!(rest.ends_with("\r\n\r\npart one, part two"), "{}", rest);
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use rustls::crypto::ring::{default_provider, sign::any_supported_type};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_rustls::TlsAcceptor;
+use tracing::{error, info};
+
+use crate::server::config::env_or;
+use crate::utils::error::ApplicationError;
+
+const DEFAULT_RELOAD_INTERVAL: u64 = 30;
+
+// A PEM certificate chain and the PEM private key that goes with it
+#[derive(Debug, Clone)]
+pub struct CertificatePaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    // Served to clients that send no SNI name, or one without its own entry
+    pub default_certificate: CertificatePaths,
+    // Host names (or "*.example.com" wildcards) with their own certificate
+    pub sni_certificates: Vec<(String, CertificatePaths)>,
+    // How often the files are checked for changes
+    pub reload_interval: Duration,
+    // Plain HTTP port that redirects everything to HTTPS, if any
+    pub redirect_port: Option<u16>,
+}
+
+impl TlsConfig {
+    // HTTPS is on when TLS_CERT_FILE and TLS_KEY_FILE are set. Further
+    // certificates are picked by SNI from
+    //   TLS_SNI_CERTS="api.example.com=api.pem,api.key;*.example.com=wild.pem,wild.key"
+    // and the files are re-read when they change, every TLS_RELOAD_INTERVAL
+    // seconds at most. HTTP_REDIRECT_PORT adds a plain listener that sends
+    // every request over to HTTPS.
+    pub fn from_env() -> Result<Option<Self>, ApplicationError> {
+        let (cert, key) = match (env::var("TLS_CERT_FILE"), env::var("TLS_KEY_FILE")) {
+            (Ok(cert), Ok(key)) => (cert, key),
+            (Err(_), Err(_)) => return Ok(None),
+            (Ok(_), Err(_)) => return Err(ApplicationError::MissingEnvVar("TLS_KEY_FILE".to_string())),
+            (Err(_), Ok(_)) => return Err(ApplicationError::MissingEnvVar("TLS_CERT_FILE".to_string())),
+        };
+
+        let sni_certificates = match env::var("TLS_SNI_CERTS") {
+            Ok(entries) => parse_sni_certificates(&entries)?,
+            Err(_) => Vec::new(),
+        };
+
+        let redirect_port = match env::var("HTTP_REDIRECT_PORT") {
+            Ok(port) => Some(
+                port.parse::<u16>()
+                    .map_err(|_| ApplicationError::InvalidEnvVar(format!("HTTP_REDIRECT_PORT: '{}' is not a port", port)))?,
+            ),
+            Err(_) => None,
+        };
+
+        Ok(Some(Self {
+            default_certificate: CertificatePaths { cert: cert.into(), key: key.into() },
+            sni_certificates,
+            reload_interval: Duration::from_secs(env_or("TLS_RELOAD_INTERVAL", DEFAULT_RELOAD_INTERVAL)),
+            redirect_port,
+        }))
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.default_certificate)
+            .chain(self.sni_certificates.iter().map(|(_, paths)| paths))
+            .flat_map(|paths| [&paths.cert, &paths.key])
+    }
+}
+
+fn parse_sni_certificates(entries: &str) -> Result<Vec<(String, CertificatePaths)>, ApplicationError> {
+    entries
+        .split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let invalid = || ApplicationError::InvalidEnvVar(format!("TLS_SNI_CERTS: expected host=cert,key in '{}'", entry));
+            let (host, files) = entry.split_once('=').ok_or_else(invalid)?;
+            let (cert, key) = files.split_once(',').ok_or_else(invalid)?;
+
+            let paths = CertificatePaths { cert: cert.trim().into(), key: key.trim().into() };
+            Ok((host.trim().to_ascii_lowercase(), paths))
+        })
+        .collect()
+}
+
+struct LoadedCertificates {
+    default: Arc<CertifiedKey>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+// Picks the certificate for each handshake by the SNI name the client asked
+// for. The certificates can be swapped out underneath it at any time, so
+// renewed certificates take effect without a restart.
+pub struct CertResolver {
+    config: TlsConfig,
+    loaded: RwLock<Arc<LoadedCertificates>>,
+    // Modification times of the files at the last load attempt
+    modified: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl CertResolver {
+    // Fails when any certificate cannot be loaded, so a broken setup is
+    // caught at startup rather than at the first handshake
+    pub fn new(config: TlsConfig) -> Result<Self, ApplicationError> {
+        // Taken before reading, so a change made while loading is picked up
+        // by the next check
+        let modified = modification_times(&config);
+        let loaded = load_certificates(&config)?;
+
+        Ok(Self { config, loaded: RwLock::new(Arc::new(loaded)), modified: Mutex::new(modified) })
+    }
+
+    // Reloads every certificate if any of the files changed. A failed reload
+    // (say, the certificate was replaced but the key not yet) keeps serving
+    // the old certificates and is tried again once the files change again.
+    pub fn reload_if_changed(&self) -> Result<bool, ApplicationError> {
+        let modified = modification_times(&self.config);
+        {
+            let mut last_attempt = self.modified.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if *last_attempt == modified {
+                return Ok(false);
+            }
+            *last_attempt = modified;
+        }
+
+        let loaded = load_certificates(&self.config)?;
+        *self.loaded.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(loaded);
+        Ok(true)
+    }
+
+    fn loaded(&self) -> Arc<LoadedCertificates> {
+        Arc::clone(&self.loaded.read().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let loaded = self.loaded();
+
+        let Some(name) = client_hello.server_name().map(str::to_ascii_lowercase) else {
+            return Some(Arc::clone(&loaded.default));
+        };
+
+        // An exact entry wins over a wildcard for the parent domain
+        let wildcard = name.split_once('.').map(|(_, parent)| format!("*.{}", parent));
+        let certificate = loaded
+            .by_name
+            .get(&name)
+            .or_else(|| wildcard.and_then(|wildcard| loaded.by_name.get(&wildcard)))
+            .unwrap_or(&loaded.default);
+
+        Some(Arc::clone(certificate))
+    }
+}
+
+impl fmt::Debug for CertResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertResolver").field("config", &self.config).finish_non_exhaustive()
+    }
+}
+
+fn load_certificates(config: &TlsConfig) -> Result<LoadedCertificates, ApplicationError> {
+    let default = load_certified_key(&config.default_certificate)?;
+    let mut by_name = HashMap::new();
+    for (host, paths) in &config.sni_certificates {
+        by_name.insert(host.clone(), load_certified_key(paths)?);
+    }
+
+    Ok(LoadedCertificates { default, by_name })
+}
+
+fn load_certified_key(paths: &CertificatePaths) -> Result<Arc<CertifiedKey>, ApplicationError> {
+    let invalid = |path: &PathBuf, e: &dyn fmt::Display| ApplicationError::TlsError(format!("{}: {}", path.display(), e));
+
+    let chain = CertificateDer::pem_file_iter(&paths.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(&paths.cert, &e))?;
+    if chain.is_empty() {
+        return Err(invalid(&paths.cert, &"no certificates found"));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(&paths.key).map_err(|e| invalid(&paths.key, &e))?;
+    let signing_key = any_supported_type(&key).map_err(|e| invalid(&paths.key, &e))?;
+
+    let certified_key = CertifiedKey::new(chain, signing_key);
+    // Catches a key that belongs to a different certificate
+    certified_key.keys_match().map_err(|e| invalid(&paths.cert, &e))?;
+
+    Ok(Arc::new(certified_key))
+}
+
+fn modification_times(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+    config
+        .paths()
+        .map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
+
+// A TLS acceptor that offers `alpn_protocols`, in order of preference, to
+// clients that ask for ALPN
+pub fn tls_acceptor(resolver: Arc<CertResolver>, alpn_protocols: &[&[u8]]) -> Result<TlsAcceptor, ApplicationError> {
+    let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| ApplicationError::TlsError(e.to_string()))?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+
+    config.alpn_protocols = alpn_protocols.iter().map(|protocol| protocol.to_vec()).collect();
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+pub fn spawn_certificate_reloader(resolver: Arc<CertResolver>, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            match resolver.reload_if_changed() {
+                Ok(false) => {}
+                Ok(true) => info!("Reloaded TLS certificates"),
+                Err(e) => error!("Failed to reload TLS certificates, keeping the current ones: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use rustls::RootCertStore;
+    use rustls::pki_types::ServerName;
+    use tokio_rustls::TlsConnector;
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tls-test-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // A fresh self-signed certificate for `hosts`, written to `name`.pem and
+    // `name`.key
+    fn write_certificate(dir: &Path, name: &str, hosts: &[&str]) -> (CertificatePaths, CertificateDer<'static>) {
+        let hosts: Vec<String> = hosts.iter().map(|host| host.to_string()).collect();
+        let generated = rcgen::generate_simple_self_signed(hosts).unwrap();
+
+        let paths = CertificatePaths { cert: dir.join(format!("{}.pem", name)), key: dir.join(format!("{}.key", name)) };
+        fs::write(&paths.cert, generated.cert.pem()).unwrap();
+        fs::write(&paths.key, generated.key_pair.serialize_pem()).unwrap();
+        (paths, generated.cert.der().clone())
+    }
+
+    // Moves the modification time forward, as a rewrite within the same clock
+    // tick might not
+    fn touch(paths: &CertificatePaths, seconds_ahead: u64) {
+        for path in [&paths.cert, &paths.key] {
+            let file = fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(SystemTime::now() + Duration::from_secs(seconds_ahead)).unwrap();
+        }
+    }
+
+    fn config(default_certificate: CertificatePaths, sni_certificates: Vec<(String, CertificatePaths)>) -> TlsConfig {
+        TlsConfig { default_certificate, sni_certificates, reload_interval: Duration::from_secs(1), redirect_port: None }
+    }
+
+    // Connects to `acceptor` as `server_name`, trusting only `trusted`, and
+    // returns the certificate the server presented
+    async fn handshake(
+        acceptor: &TlsAcceptor,
+        trusted: &[&CertificateDer<'static>],
+        server_name: &str,
+        send_sni: bool,
+    ) -> CertificateDer<'static> {
+        let mut roots = RootCertStore::empty();
+        for certificate in trusted {
+            roots.add((*certificate).clone()).unwrap();
+        }
+
+        let mut client = rustls::ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client.enable_sni = send_sni;
+
+        let (client_io, server_io) = tokio::io::duplex(16 * 1024);
+        let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+        let (server, client) = tokio::join!(
+            acceptor.accept(server_io),
+            TlsConnector::from(Arc::new(client)).connect(server_name, client_io)
+        );
+        server.unwrap();
+
+        client.unwrap().get_ref().1.peer_certificates().unwrap()[0].clone()
+    }
+
+    #[tokio::test]
+    async fn picks_certificate_by_sni_name() {
+        let dir = temp_dir("sni");
+        let (default_paths, default_cert) = write_certificate(&dir, "default", &["default.test", "other.test"]);
+        let (wildcard_paths, wildcard_cert) = write_certificate(&dir, "wildcard", &["*.example.test"]);
+        let (exact_paths, exact_cert) = write_certificate(&dir, "exact", &["api.example.test"]);
+
+        // The wildcard comes first to show the exact entry wins regardless
+        let resolver = CertResolver::new(config(
+            default_paths,
+            vec![("*.example.test".to_string(), wildcard_paths), ("api.example.test".to_string(), exact_paths)],
+        ))
+        .unwrap();
+        let acceptor = tls_acceptor(Arc::new(resolver), &[]).unwrap();
+        let trusted = [&default_cert, &wildcard_cert, &exact_cert];
+
+        let cases = [
+            ("api.example.test", true, &exact_cert),
+            ("Api.Example.Test", true, &exact_cert),
+            ("www.example.test", true, &wildcard_cert),
+            ("other.test", true, &default_cert),
+            ("default.test", false, &default_cert),
+        ];
+        for (server_name, send_sni, expected) in cases {
+            let certificate = handshake(&acceptor, &trusted, server_name, send_sni).await;
+            assert!(certificate == *expected, "wrong certificate for {}", server_name);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reloads_changed_certificates_and_keeps_the_old_ones_on_failure() {
+        let dir = temp_dir("reload");
+        let (paths, first_cert) = write_certificate(&dir, "server", &["localhost"]);
+        let resolver = Arc::new(CertResolver::new(config(paths.clone(), Vec::new())).unwrap());
+        let acceptor = tls_acceptor(Arc::clone(&resolver), &[]).unwrap();
+
+        assert!(!resolver.reload_if_changed().unwrap());
+        assert!(handshake(&acceptor, &[&first_cert], "localhost", true).await == first_cert);
+
+        let (_, second_cert) = write_certificate(&dir, "server", &["localhost"]);
+        touch(&paths, 10);
+        assert!(resolver.reload_if_changed().unwrap());
+        assert!(!resolver.reload_if_changed().unwrap());
+        assert!(handshake(&acceptor, &[&second_cert], "localhost", true).await == second_cert);
+
+        // A certificate whose key has not been replaced yet
+        write_certificate(&dir, "other", &["localhost"]);
+        fs::copy(dir.join("other.pem"), &paths.cert).unwrap();
+        touch(&paths, 20);
+        assert!(matches!(resolver.reload_if_changed(), Err(ApplicationError::TlsError(_))));
+        assert!(!resolver.reload_if_changed().unwrap());
+        assert!(handshake(&acceptor, &[&second_cert], "localhost", true).await == second_cert);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_sni_certificate_entries() {
+        let entries = parse_sni_certificates(" API.example.com = api.pem , api.key ; ;*.example.com=wild.pem,wild.key").unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, "api.example.com");
+        assert_eq!(entries[0].1.cert, PathBuf::from("api.pem"));
+        assert_eq!(entries[0].1.key, PathBuf::from("api.key"));
+        assert_eq!(entries[1].0, "*.example.com");
+
+        assert!(parse_sni_certificates("api.example.com=api.pem").is_err());
+        assert!(parse_sni_certificates("api.pem,api.key").is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use crate::server::methods::HttpMethod;
+use crate::server::pattern::{PathPattern, Segment};
+use crate::utils::error::ApplicationError;
+
+// Route table compiled into a trie of path segments. Lookups only walk the
+// branches that can match the requested path, so their cost depends on the
+// depth of the path rather than on how many routes are registered. At every
+// level a static segment is tried before a parameter, and a parameter before
+// a wildcard, which makes the winner independent of registration order.
+pub struct RouteTree<T> {
+    root: Node<T>,
+    methods: Vec<HttpMethod>,
+}
+
+struct Node<T> {
+    statics: HashMap<String, Node<T>>,
+    param: Option<(String, Box<Node<T>>)>,
+    wildcard: Option<(String, HashMap<HttpMethod, Endpoint<T>>)>,
+    endpoints: HashMap<HttpMethod, Endpoint<T>>,
+}
+
+struct Endpoint<T> {
+    pattern: String,
+    value: T,
+}
+
+pub struct Match<'a, T> {
+    pub value: &'a T,
+    pub pattern: &'a str,
+    pub params: HashMap<String, String>,
+}
+
+impl<T> RouteTree<T> {
+    pub fn new() -> Self {
+        Self { root: Node::new(), methods: Vec::new() }
+    }
+
+    pub fn insert(&mut self, method: HttpMethod, pattern: &PathPattern, value: T) -> Result<(), ApplicationError> {
+        if !self.methods.contains(&method) {
+            self.methods.push(method.clone());
+            self.methods.sort();
+        }
+
+        let mut node = &mut self.root;
+
+        for segment in pattern.segments() {
+            match segment {
+                Segment::Static(text) => {
+                    node = node.statics.entry(text.clone()).or_insert_with(Node::new);
+                }
+                Segment::Param(name) => {
+                    let (existing, child) = node.param.get_or_insert_with(|| (name.clone(), Box::new(Node::new())));
+                    if existing != name {
+                        return Err(ambiguous(pattern, existing));
+                    }
+                    node = child;
+                }
+                Segment::Wildcard(name) => {
+                    let (existing, endpoints) = node.wildcard.get_or_insert_with(|| (name.clone(), HashMap::new()));
+                    if existing != name {
+                        return Err(ambiguous(pattern, existing));
+                    }
+                    return add_endpoint(endpoints, method, pattern, value);
+                }
+            }
+        }
+
+        add_endpoint(&mut node.endpoints, method, pattern, value)
+    }
+
+    pub fn find(&self, method: &HttpMethod, path: &str) -> Option<Match<'_, T>> {
+        let segments: Vec<&str> = split_path(path).collect();
+        let mut captured = Vec::new();
+
+        let endpoint = self.root.find(method, &segments, &mut captured)?;
+
+        Some(Match {
+            value: &endpoint.value,
+            pattern: &endpoint.pattern,
+            params: captured.into_iter().collect(),
+        })
+    }
+
+    // Every method some route would answer for this path
+    pub fn allowed_methods(&self, path: &str) -> Vec<HttpMethod> {
+        self.methods
+            .iter()
+            .filter(|method| self.find(method, path).is_some())
+            .cloned()
+            .collect()
+    }
+
+    pub fn methods(&self) -> &[HttpMethod] {
+        &self.methods
+    }
+
+    // Takes the tree apart again into (method, pattern, value) triples
+    pub fn into_routes(self) -> Vec<(HttpMethod, String, T)> {
+        let mut routes = Vec::new();
+        self.root.collect(&mut routes);
+        routes
+    }
+}
+
+impl<T> Default for RouteTree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Node<T> {
+    fn new() -> Self {
+        Self {
+            statics: HashMap::new(),
+            param: None,
+            wildcard: None,
+            endpoints: HashMap::new(),
+        }
+    }
+
+    fn collect(self, routes: &mut Vec<(HttpMethod, String, T)>) {
+        let endpoints = self.endpoints
+            .into_iter()
+            .chain(self.wildcard.into_iter().flat_map(|(_, endpoints)| endpoints));
+
+        for (method, endpoint) in endpoints {
+            routes.push((method, endpoint.pattern, endpoint.value));
+        }
+
+        for child in self.statics.into_values() {
+            child.collect(routes);
+        }
+
+        if let Some((_, child)) = self.param {
+            child.collect(routes);
+        }
+    }
+
+    fn find<'a>(
+        &'a self,
+        method: &HttpMethod,
+        segments: &[&str],
+        captured: &mut Vec<(String, String)>,
+    ) -> Option<&'a Endpoint<T>> {
+        let Some((first, rest)) = segments.split_first() else {
+            return self.endpoints.get(method);
+        };
+
+        if let Some(child) = self.statics.get(*first)
+            && let Some(endpoint) = child.find(method, rest, captured)
+        {
+            return Some(endpoint);
+        }
+
+        if let Some((name, child)) = &self.param
+            && !first.is_empty()
+        {
+            captured.push((name.clone(), first.to_string()));
+            if let Some(endpoint) = child.find(method, rest, captured) {
+                return Some(endpoint);
+            }
+            captured.pop();
+        }
+
+        // Like a parameter, a wildcard never captures an empty segment: a
+        // leading one would turn "/css//etc/passwd" into an absolute path
+        if let Some((name, endpoints)) = &self.wildcard
+            && !segments.iter().any(|segment| segment.is_empty())
+            && let Some(endpoint) = endpoints.get(method)
+        {
+            captured.push((name.clone(), segments.join("/")));
+            return Some(endpoint);
+        }
+
+        None
+    }
+}
+
+fn add_endpoint<T>(
+    endpoints: &mut HashMap<HttpMethod, Endpoint<T>>,
+    method: HttpMethod,
+    pattern: &PathPattern,
+    value: T,
+) -> Result<(), ApplicationError> {
+    if let Some(existing) = endpoints.get(&method) {
+        return Err(ApplicationError::RouteConflict(format!(
+            "{:?} '{}' is already handled by '{}'",
+            method,
+            pattern.as_str(),
+            existing.pattern
+        )));
+    }
+
+    endpoints.insert(method, Endpoint { pattern: pattern.as_str().to_string(), value });
+    Ok(())
+}
+
+// Capturing the same position under different names (`/{id}/edit` next to
+// `/{ticker}`) would make the parameter a handler sees depend on which route
+// happened to win, so such patterns cannot live in one tree.
+fn ambiguous(pattern: &PathPattern, existing: &str) -> ApplicationError {
+    ApplicationError::RouteConflict(format!(
+        "'{}' names a segment that is already captured as '{}'",
+        pattern.as_str(),
+        existing
+    ))
+}
+
+pub(crate) fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    let trimmed = path.strip_prefix('/').unwrap_or(path);
+    trimmed.split('/').filter(move |_| !trimmed.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(patterns: &[&str]) -> RouteTree<String> {
+        let mut tree = RouteTree::new();
+        for pattern in patterns {
+            tree.insert(HttpMethod::GET, &PathPattern::parse(pattern).unwrap(), pattern.to_string()).unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn wildcard_rejects_empty_segments() {
+        let tree = tree(&["/css/*path"]);
+
+        assert_eq!(tree.find(&HttpMethod::GET, "/css/site/styles.css").unwrap().params["path"], "site/styles.css");
+        assert!(tree.find(&HttpMethod::GET, "/css//etc/hostname").is_none());
+        assert!(tree.find(&HttpMethod::GET, "/css/site//styles.css").is_none());
+        assert!(tree.find(&HttpMethod::GET, "/css/site/").is_none());
+        assert!(tree.find(&HttpMethod::GET, "/css").is_none());
+    }
+
+    #[test]
+    fn param_rejects_empty_segments() {
+        let tree = tree(&["/{ticker}/history"]);
+
+        assert!(tree.find(&HttpMethod::GET, "/AAPL/history").is_some());
+        assert!(tree.find(&HttpMethod::GET, "//history").is_none());
+    }
+
+    #[test]
+    fn static_beats_param_beats_wildcard_in_any_order() {
+        let patterns = ["/files/*rest", "/files/{name}", "/files/latest"];
+
+        for order in [[0, 1, 2], [2, 1, 0], [1, 2, 0]] {
+            let tree = tree(&order.map(|i| patterns[i]));
+            let pattern = |path| tree.find(&HttpMethod::GET, path).map(|found| found.pattern);
+
+            assert_eq!(pattern("/files/latest"), Some("/files/latest"));
+            assert_eq!(pattern("/files/report"), Some("/files/{name}"));
+            assert_eq!(pattern("/files/2024/report"), Some("/files/*rest"));
+        }
+    }
+
+    #[test]
+    fn backtracks_out_of_a_static_branch() {
+        let tree = tree(&["/symbols/{ticker}/history", "/symbols/popular"]);
+
+        let found = tree.find(&HttpMethod::GET, "/symbols/popular/history").unwrap();
+        assert_eq!(found.pattern, "/symbols/{ticker}/history");
+        assert_eq!(found.params["ticker"], "popular");
+    }
+
+    #[test]
+    fn captures_params_and_wildcards() {
+        let tree = tree(&["/users/{id}/files/*path"]);
+
+        let found = tree.find(&HttpMethod::GET, "/users/42/files/a/b.txt").unwrap();
+        assert_eq!(found.params["id"], "42");
+        assert_eq!(found.params["path"], "a/b.txt");
+        assert_eq!(found.params.len(), 2);
+    }
+
+    #[test]
+    fn matches_methods_separately() {
+        let mut tree = tree(&["/keys"]);
+        tree.insert(HttpMethod::POST, &PathPattern::parse("/keys").unwrap(), "post".to_string()).unwrap();
+
+        assert_eq!(tree.find(&HttpMethod::POST, "/keys").unwrap().value, "post");
+        assert!(tree.find(&HttpMethod::DELETE, "/keys").is_none());
+        assert_eq!(tree.allowed_methods("/keys"), vec![HttpMethod::GET, HttpMethod::POST]);
+        assert!(tree.allowed_methods("/other").is_empty());
+    }
+
+    #[test]
+    fn rejects_conflicting_routes() {
+        let mut tree = tree(&["/{ticker}", "/api/*rest"]);
+
+        let insert = |tree: &mut RouteTree<String>, pattern| tree.insert(HttpMethod::GET, &PathPattern::parse(pattern).unwrap(), String::new());
+        assert!(matches!(insert(&mut tree, "/{ticker}"), Err(ApplicationError::RouteConflict(_))));
+        assert!(matches!(insert(&mut tree, "/{id}/edit"), Err(ApplicationError::RouteConflict(_))));
+        assert!(matches!(insert(&mut tree, "/api/*path"), Err(ApplicationError::RouteConflict(_))));
+    }
+}
@@ -0,0 +1,98 @@
+use crate::utils::error::ApplicationError;
+
+// Decodes `%XX` escapes, and `+` as a space when `plus_as_space` is set (as
+// it is for query strings and form bodies, but not for paths).
+pub fn percent_decode(input: &str, plus_as_space: bool) -> Result<String, ApplicationError> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let escape = bytes
+                    .get(index + 1..index + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| ApplicationError::UrlParseError(format!("invalid percent-encoding in '{}'", input)))?;
+                decoded.push(escape);
+                index += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                index += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded)
+        .map_err(|_| ApplicationError::UrlParseError(format!("'{}' does not decode to UTF-8", input)))
+}
+
+// Escapes everything but RFC 3986 unreserved characters, so the result can
+// go into any part of a URL
+pub fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+
+    for byte in input.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    encoded
+}
+
+// Parses `application/x-www-form-urlencoded` data into decoded pairs, keeping
+// repeated keys and their order. A key without '=' gets an empty value.
+pub fn parse_urlencoded(input: &str) -> Result<Vec<(String, String)>, ApplicationError> {
+    input
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(key, true)?, percent_decode(value, true)?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_escapes_and_plus() {
+        assert_eq!(percent_decode("a%20b%2Fc%e2%82%ac", false).unwrap(), "a b/c€");
+        assert_eq!(percent_decode("a+b", false).unwrap(), "a+b");
+        assert_eq!(percent_decode("a+b", true).unwrap(), "a b");
+        assert_eq!(percent_decode("%2B", true).unwrap(), "+");
+    }
+
+    #[test]
+    fn rejects_broken_escapes() {
+        for input in ["%", "%2", "%zz", "abc%4", "%ff", "%c3%28"] {
+            assert!(percent_decode(input, false).is_err(), "accepted {}", input);
+        }
+    }
+
+    #[test]
+    fn encode_round_trips() {
+        let input = "a b/c?d=e&f€~";
+        assert_eq!(percent_encode(input), "a%20b%2Fc%3Fd%3De%26f%E2%82%AC~");
+        assert_eq!(percent_decode(&percent_encode(input), false).unwrap(), input);
+    }
+
+    #[test]
+    fn parses_form_pairs_in_order() {
+        let pairs = parse_urlencoded("name=a+b&empty=&flag&name=c%26d&&").unwrap();
+
+        let expected = [("name", "a b"), ("empty", ""), ("flag", ""), ("name", "c&d")];
+        assert_eq!(pairs, expected.map(|(key, value)| (key.to_string(), value.to_string())));
+    }
+}
@@ -0,0 +1,21 @@
+use crate::utils::error::ApplicationError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+    Http2,
+}
+
+impl TryFrom<&str> for HttpVersion {
+    type Error = ApplicationError;
+
+    fn try_from(version_str: &str) -> Result<Self, Self::Error> {
+        match version_str {
+            "HTTP/1.0" => Ok(HttpVersion::Http10),
+            "HTTP/1.1" => Ok(HttpVersion::Http11),
+            "HTTP/2" => Ok(HttpVersion::Http2),
+            _ => Err(ApplicationError::InvalidHttpVersion(version_str.to_string())),
+        }
+    }
+}
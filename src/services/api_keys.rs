@@ -0,0 +1,97 @@
+use std::env;
+use std::sync::Arc;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+
+use crate::models::api_key::{ApiKey, ApiScope};
+use crate::services::database::Database;
+use crate::utils::error::ApplicationError;
+
+const KEY_PREFIX: &str = "ak_";
+const KEY_BYTES: usize = 32;
+const DISPLAY_PREFIX_LEN: usize = 8;
+
+// Mints, checks and revokes API keys. Keys are 256 random bits, so a plain
+// SHA-256 is enough to store them safely; unlike passwords they cannot be
+// guessed from a dictionary and need no slow hash.
+pub struct ApiKeys {
+    database: Arc<Database>,
+    // ADMIN_API_KEY, which works without being in the database so the first
+    // real keys can be minted with it
+    bootstrap_hash: Option<String>,
+}
+
+impl ApiKeys {
+    pub fn new(database: Arc<Database>, bootstrap_key: Option<&str>) -> Self {
+        Self { database, bootstrap_hash: bootstrap_key.map(hash_api_key) }
+    }
+
+    pub fn from_env(database: Arc<Database>) -> Self {
+        let bootstrap_key = env::var("ADMIN_API_KEY").ok().filter(|key| !key.is_empty());
+        Self::new(database, bootstrap_key.as_deref())
+    }
+
+    // Returns the key itself, which is shown to the caller once and never
+    // stored, along with what is kept about it
+    pub async fn mint(
+        &self,
+        name: &str,
+        scopes: &[ApiScope],
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(String, ApiKey), ApplicationError> {
+        let mut bytes = [0; KEY_BYTES];
+        OsRng.fill_bytes(&mut bytes);
+        let key = format!("{}{}", KEY_PREFIX, URL_SAFE_NO_PAD.encode(bytes));
+        let prefix = &key[..KEY_PREFIX.len() + DISPLAY_PREFIX_LEN];
+
+        let api_key = self
+            .database
+            .create_api_key(name, &hash_api_key(&key), prefix, scopes, expires_at)
+            .await?;
+
+        Ok((key, api_key))
+    }
+
+    // The key's record if it exists and is neither expired nor revoked
+    pub async fn authenticate(&self, key: &str) -> Result<Option<ApiKey>, ApplicationError> {
+        let key_hash = hash_api_key(key);
+
+        if self.bootstrap_hash.as_deref() == Some(key_hash.as_str()) {
+            return Ok(Some(bootstrap_key()));
+        }
+
+        let api_key = self.database.get_api_key_by_hash(&key_hash).await?;
+        Ok(api_key.filter(|api_key| api_key.is_active(Utc::now())))
+    }
+
+    pub async fn list(&self) -> Result<Vec<ApiKey>, ApplicationError> {
+        self.database.get_all_api_keys().await
+    }
+
+    pub async fn revoke(&self, id: i64) -> Result<bool, ApplicationError> {
+        self.database.revoke_api_key(id).await
+    }
+}
+
+fn hash_api_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn bootstrap_key() -> ApiKey {
+    ApiKey {
+        id: 0,
+        name: "ADMIN_API_KEY".to_string(),
+        prefix: String::new(),
+        scopes: vec![ApiScope::Admin],
+        created_at: DateTime::UNIX_EPOCH,
+        expires_at: None,
+        revoked_at: None,
+    }
+}
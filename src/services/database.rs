@@ -1,6 +1,10 @@
+use crate::models::api_key::{ApiKey, ApiScope};
+use crate::models::session::SessionRecord;
 use crate::models::symbol::Symbol;
+use crate::models::user::User;
 use crate::utils::error::ApplicationError;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::sqlite::SqliteRow;
 use sqlx::{Pool, Row, Sqlite};
 use sqlx::migrate::MigrateDatabase;
 use tracing::info;
@@ -41,6 +45,57 @@ impl Database {
         .execute(pool)
         .await?;
 
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS sessions (
+                id TEXT PRIMARY KEY,
+                data TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                expires_at TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS sessions_expires_at ON sessions (expires_at)")
+            .execute(pool)
+            .await?;
+
+        // Usernames are unique regardless of case, so "Alice" cannot register
+        // next to "alice"
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS users (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                username TEXT NOT NULL UNIQUE COLLATE NOCASE,
+                password_hash TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        // Only a SHA-256 of each key is kept, so the table is useless to
+        // anyone who manages to read it
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS api_keys (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                key_hash TEXT NOT NULL UNIQUE,
+                prefix TEXT NOT NULL,
+                scopes TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                expires_at TEXT,
+                revoked_at TEXT
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
         Ok(())
     }
 
@@ -98,7 +153,7 @@ impl Database {
         for row in rows {
             let last_updated_str: String = row.get("last_updated");
             let last_updated = DateTime::parse_from_rfc3339(&last_updated_str)
-                .map_err(|e| ApplicationError::DateParseError(e))?
+                .map_err(ApplicationError::DateParseError)?
                 .with_timezone(&Utc);
 
             let symbol = Symbol {
@@ -141,7 +196,7 @@ impl Database {
             Some(row) => {
                 let last_updated_str: String = row.get("last_updated");
                 let last_updated = DateTime::parse_from_rfc3339(&last_updated_str)
-                    .map_err(|e| ApplicationError::DateParseError(e))?
+                    .map_err(ApplicationError::DateParseError)?
                     .with_timezone(&Utc);
 
                 let symbol = Symbol {
@@ -162,6 +217,224 @@ impl Database {
             None => Ok(None),
         }
     }
+
+    pub async fn load_session(&self, id: &str) -> Result<Option<SessionRecord>, ApplicationError> {
+        let row = sqlx::query("SELECT data, created_at, expires_at FROM sessions WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let data: String = row.get("data");
+        let created_at: String = row.get("created_at");
+        let expires_at: String = row.get("expires_at");
+
+        Ok(Some(SessionRecord {
+            data: serde_json::from_str(&data)?,
+            created_at: DateTime::parse_from_rfc3339(&created_at)?.with_timezone(&Utc),
+            expires_at: DateTime::parse_from_rfc3339(&expires_at)?.with_timezone(&Utc),
+        }))
+    }
+
+    pub async fn save_session(&self, id: &str, record: &SessionRecord) -> Result<(), ApplicationError> {
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, data, created_at, expires_at) VALUES (?, ?, ?, ?)
+            ON CONFLICT(id) DO UPDATE SET
+                data = excluded.data,
+                expires_at = excluded.expires_at
+            "#,
+        )
+        .bind(id)
+        .bind(serde_json::to_string(&record.data)?)
+        .bind(session_timestamp(record.created_at))
+        .bind(session_timestamp(record.expires_at))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_session(&self, id: &str) -> Result<(), ApplicationError> {
+        sqlx::query("DELETE FROM sessions WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> Result<u64, ApplicationError> {
+        let result = sqlx::query("DELETE FROM sessions WHERE expires_at <= ?")
+            .bind(session_timestamp(now))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    // `None` when the username is already taken
+    pub async fn create_user(&self, username: &str, password_hash: &str) -> Result<Option<User>, ApplicationError> {
+        let created_at = Utc::now();
+
+        let result = sqlx::query(
+            r#"
+            INSERT INTO users (username, password_hash, created_at) VALUES (?, ?, ?)
+            ON CONFLICT(username) DO NOTHING
+            "#,
+        )
+        .bind(username)
+        .bind(password_hash)
+        .bind(created_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(User {
+            id: result.last_insert_rowid(),
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            created_at,
+        }))
+    }
+
+    pub async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, ApplicationError> {
+        let row = sqlx::query("SELECT id, username, password_hash, created_at FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(user_from_row).transpose()
+    }
+
+    pub async fn get_user_by_id(&self, id: i64) -> Result<Option<User>, ApplicationError> {
+        let row = sqlx::query("SELECT id, username, password_hash, created_at FROM users WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(user_from_row).transpose()
+    }
+
+    pub async fn create_api_key(
+        &self,
+        name: &str,
+        key_hash: &str,
+        prefix: &str,
+        scopes: &[ApiScope],
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<ApiKey, ApplicationError> {
+        let created_at = Utc::now();
+        let scope_list: Vec<&str> = scopes.iter().map(ApiScope::as_str).collect();
+
+        let result = sqlx::query(
+            r#"
+            INSERT INTO api_keys (name, key_hash, prefix, scopes, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(name)
+        .bind(key_hash)
+        .bind(prefix)
+        .bind(scope_list.join(" "))
+        .bind(created_at.to_rfc3339())
+        .bind(expires_at.map(|expires_at| expires_at.to_rfc3339()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(ApiKey {
+            id: result.last_insert_rowid(),
+            name: name.to_string(),
+            prefix: prefix.to_string(),
+            scopes: scopes.to_vec(),
+            created_at,
+            expires_at,
+            revoked_at: None,
+        })
+    }
+
+    pub async fn get_api_key_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, ApplicationError> {
+        let row = sqlx::query(
+            r#"
+            SELECT id, name, prefix, scopes, created_at, expires_at, revoked_at
+            FROM api_keys
+            WHERE key_hash = ?
+            "#,
+        )
+        .bind(key_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(api_key_from_row).transpose()
+    }
+
+    pub async fn get_all_api_keys(&self) -> Result<Vec<ApiKey>, ApplicationError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, name, prefix, scopes, created_at, expires_at, revoked_at
+            FROM api_keys
+            ORDER BY id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(api_key_from_row).collect()
+    }
+
+    // `false` when there is no such key or it was already revoked
+    pub async fn revoke_api_key(&self, id: i64) -> Result<bool, ApplicationError> {
+        let result = sqlx::query("UPDATE api_keys SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL")
+            .bind(Utc::now().to_rfc3339())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+fn api_key_from_row(row: &SqliteRow) -> Result<ApiKey, ApplicationError> {
+    let scopes: String = row.get("scopes");
+    let created_at: String = row.get("created_at");
+    let expires_at: Option<String> = row.get("expires_at");
+    let revoked_at: Option<String> = row.get("revoked_at");
+
+    let parse_time = |time: &str| DateTime::parse_from_rfc3339(time).map(|time| time.with_timezone(&Utc));
+
+    Ok(ApiKey {
+        id: row.get("id"),
+        name: row.get("name"),
+        prefix: row.get("prefix"),
+        scopes: scopes.split_whitespace().map(str::parse).collect::<Result<_, _>>()?,
+        created_at: parse_time(&created_at)?,
+        expires_at: expires_at.as_deref().map(parse_time).transpose()?,
+        revoked_at: revoked_at.as_deref().map(parse_time).transpose()?,
+    })
+}
+
+// Row to `User`, for the queries that select every column of `users`
+fn user_from_row(row: &SqliteRow) -> Result<User, ApplicationError> {
+    let created_at: String = row.get("created_at");
+
+    Ok(User {
+        id: row.get("id"),
+        username: row.get("username"),
+        password_hash: row.get("password_hash"),
+        created_at: DateTime::parse_from_rfc3339(&created_at)?.with_timezone(&Utc),
+    })
+}
+
+// Fixed-width UTC timestamps compare correctly as text, which the expiry
+// query relies on
+fn session_timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
 }
 
 impl Clone for Database {
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::server::config::env_or;
+use crate::utils::error::ApplicationError;
+
+const DEFAULT_LEEWAY: u64 = 60;
+// As for cookie secrets; a short HS256 secret can be brute-forced offline
+// from any one token
+const MIN_SECRET_LEN: usize = 32;
+
+// The claims of a verified token. Registered claims have accessors; anything
+// else the SSO puts in (roles, email, ...) is read with `get`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Claims(Map<String, Value>);
+
+impl Claims {
+    pub fn subject(&self) -> Option<&str> {
+        self.0.get("sub").and_then(Value::as_str)
+    }
+
+    pub fn issuer(&self) -> Option<&str> {
+        self.0.get("iss").and_then(Value::as_str)
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        serde_json::from_value(self.0.get(name)?.clone()).ok()
+    }
+}
+
+enum VerificationKeys {
+    Single(DecodingKey),
+    // Keys from a JWKS file, picked by the `kid` in the token header
+    Set(HashMap<String, DecodingKey>),
+}
+
+// Checks JWTs from the SSO: the signature against locally configured keys
+// (so nothing is fetched at runtime), then `exp`, `nbf` and, when configured,
+// `iss` and `aud`. Only the configured algorithm is accepted, which rules
+// out downgrades such as an HS256 token signed with the RSA public key.
+pub struct JwtVerifier {
+    keys: VerificationKeys,
+    validation: Validation,
+}
+
+impl JwtVerifier {
+    // Configured through
+    //   JWT_ALGORITHM          HS256 or RS256; JWT is disabled when unset
+    //   JWT_SECRET             the shared secret for HS256, 32 bytes or more
+    //   JWT_PUBLIC_KEY_FILE    a PEM public key for RS256, or
+    //   JWT_JWKS_FILE          a JWKS document with one or more RS256 keys
+    //   JWT_ISSUER             required `iss`, if set
+    //   JWT_AUDIENCE           required `aud`, if set
+    //   JWT_LEEWAY             clock skew allowed on `exp`/`nbf`, in seconds
+    pub fn from_env() -> Result<Option<Self>, ApplicationError> {
+        let Ok(algorithm) = env::var("JWT_ALGORITHM") else {
+            return Ok(None);
+        };
+
+        let keys = match algorithm.as_str() {
+            "HS256" => {
+                let secret = env::var("JWT_SECRET")
+                    .map_err(|_| ApplicationError::MissingEnvVar("JWT_SECRET".to_string()))?;
+                VerificationKeys::Single(hs256_key(secret.as_bytes())?)
+            }
+            "RS256" => match (env::var("JWT_PUBLIC_KEY_FILE"), env::var("JWT_JWKS_FILE")) {
+                (Ok(path), _) => VerificationKeys::Single(load_pem(&path)?),
+                (_, Ok(path)) => VerificationKeys::Set(load_jwks(&path)?),
+                _ => return Err(ApplicationError::MissingEnvVar("JWT_PUBLIC_KEY_FILE or JWT_JWKS_FILE".to_string())),
+            },
+            other => {
+                return Err(ApplicationError::InvalidEnvVar(format!("JWT_ALGORITHM: unsupported algorithm '{}'", other)));
+            }
+        };
+
+        let algorithm = if algorithm == "HS256" { Algorithm::HS256 } else { Algorithm::RS256 };
+        Ok(Some(Self::new(
+            keys,
+            algorithm,
+            env::var("JWT_ISSUER").ok(),
+            env::var("JWT_AUDIENCE").ok(),
+            env_or("JWT_LEEWAY", DEFAULT_LEEWAY),
+        )))
+    }
+
+    fn new(keys: VerificationKeys, algorithm: Algorithm, issuer: Option<String>, audience: Option<String>, leeway: u64) -> Self {
+        let mut validation = Validation::new(algorithm);
+        validation.validate_nbf = true;
+        validation.leeway = leeway;
+
+        let mut required_claims = vec!["exp"];
+        match issuer {
+            Some(issuer) => {
+                validation.set_issuer(&[issuer]);
+                required_claims.push("iss");
+            }
+            None => validation.iss = None,
+        }
+        match audience {
+            Some(audience) => {
+                validation.set_audience(&[audience]);
+                required_claims.push("aud");
+            }
+            // Without a configured audience any `aud` in a token is accepted
+            None => validation.validate_aud = false,
+        }
+        validation.set_required_spec_claims(&required_claims);
+
+        Self { keys, validation }
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Claims, ApplicationError> {
+        let invalid = |e: jsonwebtoken::errors::Error| ApplicationError::InvalidToken(e.to_string());
+
+        let key = match &self.keys {
+            VerificationKeys::Single(key) => key,
+            VerificationKeys::Set(keys) => {
+                let header = decode_header(token).map_err(invalid)?;
+                let kid = header
+                    .kid
+                    .ok_or_else(|| ApplicationError::InvalidToken("token header has no kid".to_string()))?;
+                keys.get(&kid)
+                    .ok_or_else(|| ApplicationError::InvalidToken(format!("unknown kid '{}'", kid)))?
+            }
+        };
+
+        decode::<Claims>(token, key, &self.validation)
+            .map(|data| data.claims)
+            .map_err(invalid)
+    }
+}
+
+fn hs256_key(secret: &[u8]) -> Result<DecodingKey, ApplicationError> {
+    if secret.len() < MIN_SECRET_LEN {
+        return Err(ApplicationError::InvalidEnvVar(format!("JWT_SECRET: must be at least {} bytes long", MIN_SECRET_LEN)));
+    }
+    Ok(DecodingKey::from_secret(secret))
+}
+
+fn load_pem(path: &str) -> Result<DecodingKey, ApplicationError> {
+    let pem = fs::read(path)?;
+    DecodingKey::from_rsa_pem(&pem)
+        .map_err(|e| ApplicationError::InvalidEnvVar(format!("JWT_PUBLIC_KEY_FILE: {}: {}", path, e)))
+}
+
+fn load_jwks(path: &str) -> Result<HashMap<String, DecodingKey>, ApplicationError> {
+    let invalid = |message: String| ApplicationError::InvalidEnvVar(format!("JWT_JWKS_FILE: {}: {}", path, message));
+
+    let jwks: JwkSet = serde_json::from_slice(&fs::read(path)?).map_err(|e| invalid(e.to_string()))?;
+    let mut keys = HashMap::new();
+
+    for jwk in &jwks.keys {
+        let kid = jwk.common.key_id.clone().ok_or_else(|| invalid("every key needs a kid".to_string()))?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|e| invalid(e.to_string()))?;
+        keys.insert(kid, key);
+    }
+
+    if keys.is_empty() {
+        return Err(invalid("the key set is empty".to_string()));
+    }
+
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+    use super::*;
+
+    const SECRET: &[u8] = b"an hs256 secret of at least 32 bytes";
+
+    fn hs256_verifier() -> JwtVerifier {
+        JwtVerifier::new(
+            VerificationKeys::Single(hs256_key(SECRET).unwrap()),
+            Algorithm::HS256,
+            Some("https://sso.example.com".to_string()),
+            Some("stocks".to_string()),
+            0,
+        )
+    }
+
+    // An RS256 key set; the keys never have to verify anything, so any
+    // modulus will do
+    fn rs256_verifier() -> JwtVerifier {
+        let modulus = "x".repeat(344);
+        let key = DecodingKey::from_rsa_components(&modulus, "AQAB").unwrap();
+        let keys = HashMap::from([("rsa-1".to_string(), key)]);
+        JwtVerifier::new(VerificationKeys::Set(keys), Algorithm::RS256, None, None, 0)
+    }
+
+    fn token(header: Header, claims: Value) -> String {
+        encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    fn claims(changes: Value) -> Value {
+        let now = Utc::now().timestamp();
+        let mut claims = json!({
+            "sub": "alice",
+            "iss": "https://sso.example.com",
+            "aud": "stocks",
+            "exp": now + 300,
+            "nbf": now - 300,
+        });
+        for (name, value) in changes.as_object().unwrap() {
+            match value {
+                Value::Null => claims.as_object_mut().unwrap().remove(name),
+                value => claims.as_object_mut().unwrap().insert(name.clone(), value.clone()),
+            };
+        }
+        claims
+    }
+
+    #[test]
+    fn rejects_short_secrets() {
+        assert!(hs256_key(b"").is_err());
+        assert!(hs256_key(&[7; MIN_SECRET_LEN - 1]).is_err());
+        assert!(hs256_key(&[7; MIN_SECRET_LEN]).is_ok());
+    }
+
+    #[test]
+    fn accepts_a_valid_token() {
+        let verified = hs256_verifier().verify(&token(Header::default(), claims(json!({})))).unwrap();
+
+        assert_eq!(verified.subject(), Some("alice"));
+        assert_eq!(verified.issuer(), Some("https://sso.example.com"));
+    }
+
+    #[test]
+    fn rejects_tokens_outside_their_validity() {
+        let now = Utc::now().timestamp();
+        let verifier = hs256_verifier();
+
+        for changes in [json!({ "exp": now - 10 }), json!({ "nbf": now + 60 }), json!({ "exp": null })] {
+            let result = verifier.verify(&token(Header::default(), claims(changes.clone())));
+            assert!(matches!(result, Err(ApplicationError::InvalidToken(_))), "accepted {}", changes);
+        }
+    }
+
+    #[test]
+    fn rejects_tokens_for_someone_else() {
+        let verifier = hs256_verifier();
+
+        for changes in [
+            json!({ "iss": "https://evil.example.com" }),
+            json!({ "iss": null }),
+            json!({ "aud": "other-app" }),
+            json!({ "aud": null }),
+        ] {
+            let result = verifier.verify(&token(Header::default(), claims(changes.clone())));
+            assert!(matches!(result, Err(ApplicationError::InvalidToken(_))), "accepted {}", changes);
+        }
+
+        let forged = encode(&Header::default(), &claims(json!({})), &EncodingKey::from_secret(b"some other secret of 32 bytes...")).unwrap();
+        assert!(verifier.verify(&forged).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_kids_and_other_algorithms() {
+        let verifier = rs256_verifier();
+
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("rsa-2".to_string());
+        let result = verifier.verify(&token(header, claims(json!({}))));
+        assert!(matches!(result, Err(ApplicationError::InvalidToken(e)) if e.contains("unknown kid")));
+
+        // No kid at all
+        assert!(verifier.verify(&token(Header::new(Algorithm::HS256), claims(json!({})))).is_err());
+
+        // An HS256 token naming a known key must not be checked as HS256
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("rsa-1".to_string());
+        assert!(matches!(verifier.verify(&token(header, claims(json!({})))), Err(ApplicationError::InvalidToken(_))));
+
+        // Nor is another HMAC variant accepted in place of the configured one
+        assert!(hs256_verifier().verify(&token(Header::new(Algorithm::HS384), claims(json!({})))).is_err());
+    }
+}
@@ -1,3 +1,7 @@
 pub mod data_sync;
 mod stock_client;
-pub mod database;
\ No newline at end of file
+pub mod database;
+pub mod session_store;
+pub mod password;
+pub mod api_keys;
+pub mod jwt;
\ No newline at end of file
@@ -0,0 +1,76 @@
+use std::sync::OnceLock;
+use argon2::Argon2;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use tokio::task;
+
+use crate::utils::error::{to_app_error, ApplicationError};
+
+// Argon2id with the crate's default cost. Hashing is deliberately slow, so it
+// runs on the blocking pool instead of stalling the connection tasks.
+pub async fn hash_password(password: String) -> Result<String, ApplicationError> {
+    task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(to_app_error)
+    })
+    .await
+    .map_err(to_app_error)?
+}
+
+// `None` stands for an unknown user: a hash is still checked so the answer
+// takes as long as for a known user with the wrong password, and response
+// times do not reveal which usernames exist.
+pub async fn verify_password(password: String, password_hash: Option<String>) -> Result<bool, ApplicationError> {
+    task::spawn_blocking(move || {
+        let (password_hash, known_user) = match password_hash {
+            Some(password_hash) => (password_hash, true),
+            None => (dummy_hash().to_string(), false),
+        };
+
+        let parsed = PasswordHash::new(&password_hash).map_err(to_app_error)?;
+        let matches = Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok();
+
+        Ok(known_user && matches)
+    })
+    .await
+    .map_err(to_app_error)?
+}
+
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    DUMMY_HASH.get_or_init(|| {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(b"not a real password", &salt)
+            .expect("hashing a constant password cannot fail")
+            .to_string()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn hashes_verify_only_the_same_password() {
+        let hash = hash_password("correct horse".to_string()).await.unwrap();
+
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(!hash.contains("correct horse"));
+        assert!(verify_password("correct horse".to_string(), Some(hash.clone())).await.unwrap());
+        assert!(!verify_password("correct horsE".to_string(), Some(hash.clone())).await.unwrap());
+
+        // Salted, so the same password never hashes the same way twice
+        assert_ne!(hash, hash_password("correct horse".to_string()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn unknown_users_never_verify() {
+        assert!(!verify_password("not a real password".to_string(), None).await.unwrap());
+        assert!(!verify_password(String::new(), None).await.unwrap());
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::task::JoinHandle;
+use tokio::time;
+use tracing::{error, info};
+
+use crate::models::session::SessionRecord;
+use crate::services::database::Database;
+use crate::utils::error::ApplicationError;
+
+// Where the session middleware keeps session data between requests
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn load(&self, id: &str) -> Result<Option<SessionRecord>, ApplicationError>;
+
+    async fn save(&self, id: &str, record: &SessionRecord) -> Result<(), ApplicationError>;
+
+    async fn delete(&self, id: &str) -> Result<(), ApplicationError>;
+
+    // Removes every session that expired by `now` and returns how many
+    async fn delete_expired(&self, now: DateTime<Utc>) -> Result<u64, ApplicationError>;
+}
+
+// Sessions that live as long as the process, for development and tests
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<String, SessionRecord>>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn sessions(&self) -> std::sync::MutexGuard<'_, HashMap<String, SessionRecord>> {
+        // A panic elsewhere cannot leave a map of plain values half-updated
+        self.sessions.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn load(&self, id: &str) -> Result<Option<SessionRecord>, ApplicationError> {
+        Ok(self.sessions().get(id).cloned())
+    }
+
+    async fn save(&self, id: &str, record: &SessionRecord) -> Result<(), ApplicationError> {
+        self.sessions().insert(id.to_string(), record.clone());
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), ApplicationError> {
+        self.sessions().remove(id);
+        Ok(())
+    }
+
+    async fn delete_expired(&self, now: DateTime<Utc>) -> Result<u64, ApplicationError> {
+        let mut sessions = self.sessions();
+        let before = sessions.len();
+        sessions.retain(|_, record| !record.is_expired(now));
+        Ok((before - sessions.len()) as u64)
+    }
+}
+
+// Sessions in the application database, so they survive restarts
+pub struct SqliteSessionStore {
+    database: Arc<Database>,
+}
+
+impl SqliteSessionStore {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait]
+impl SessionStore for SqliteSessionStore {
+    async fn load(&self, id: &str) -> Result<Option<SessionRecord>, ApplicationError> {
+        self.database.load_session(id).await
+    }
+
+    async fn save(&self, id: &str, record: &SessionRecord) -> Result<(), ApplicationError> {
+        self.database.save_session(id, record).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), ApplicationError> {
+        self.database.delete_session(id).await
+    }
+
+    async fn delete_expired(&self, now: DateTime<Utc>) -> Result<u64, ApplicationError> {
+        self.database.delete_expired_sessions(now).await
+    }
+}
+
+// Expired sessions are never loaded again, but nothing else would remove
+// the ones whose owners simply stopped coming back
+pub fn spawn_session_sweeper(store: Arc<dyn SessionStore>, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            match store.delete_expired(Utc::now()).await {
+                Ok(0) => {}
+                Ok(removed) => info!("Removed {} expired sessions", removed),
+                Err(e) => error!("Failed to remove expired sessions: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration as ChronoDuration;
+    use super::*;
+
+    fn record(expires_in_minutes: i64) -> SessionRecord {
+        let now = Utc::now();
+        SessionRecord { data: HashMap::new(), created_at: now, expires_at: now + ChronoDuration::minutes(expires_in_minutes) }
+    }
+
+    #[tokio::test]
+    async fn deletes_only_expired_sessions() {
+        let store = MemorySessionStore::new();
+        store.save("expired", &record(-1)).await.unwrap();
+        store.save("live", &record(10)).await.unwrap();
+
+        assert_eq!(store.delete_expired(Utc::now()).await.unwrap(), 1);
+        assert!(store.load("expired").await.unwrap().is_none());
+        assert!(store.load("live").await.unwrap().is_some());
+        assert_eq!(store.delete_expired(Utc::now()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn sweeper_removes_expired_sessions_in_the_background() {
+        let store = Arc::new(MemorySessionStore::new());
+        store.save("expired", &record(-1)).await.unwrap();
+        store.save("live", &record(10)).await.unwrap();
+
+        let sweeper = spawn_session_sweeper(Arc::clone(&store) as Arc<dyn SessionStore>, Duration::from_millis(10));
+        time::sleep(Duration::from_millis(50)).await;
+        sweeper.abort();
+
+        assert!(store.load("expired").await.unwrap().is_none());
+        assert!(store.load("live").await.unwrap().is_some());
+    }
+}
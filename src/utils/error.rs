@@ -1,4 +1,3 @@
-use std::error::Error;
 use std::io;
 use askama;
 use chrono;
@@ -17,6 +16,9 @@ pub enum ApplicationError {
     #[error("Invalid HTTP method: {0}")]
     InvalidHttpMethod(String),
 
+    #[error("Invalid HTTP version: {0}")]
+    InvalidHttpVersion(String),
+
     #[error("Invalid request line")]
     InvalidRequestLine,
 
@@ -26,12 +28,51 @@ pub enum ApplicationError {
     #[error("Invalid header format")]
     InvalidHeaderFormat,
 
+    #[error("Invalid header: {0}")]
+    InvalidHeader(String),
+
+    #[error("Invalid cookie: {0}")]
+    InvalidCookie(String),
+
+    #[error("Request header fields too large")]
+    HeadersTooLarge,
+
+    #[error("Request body too large")]
+    PayloadTooLarge,
+
+    #[error("Unsupported transfer encoding: {0}")]
+    UnsupportedTransferEncoding(String),
+
+    #[error("Timed out waiting for the request")]
+    RequestTimeout,
+
     #[error("Missing required headers")]
     MissingRequiredHeaders,
 
     #[error("Error parsing URL: {0}")]
     UrlParseError(String),
 
+    #[error("{message}")]
+    RequestRejected { status: u16, message: String },
+
+    #[error("Invalid route pattern: {0}")]
+    InvalidRoutePattern(String),
+
+    #[error("Conflicting routes: {0}")]
+    RouteConflict(String),
+
+    #[error("Invalid token: {0}")]
+    InvalidToken(String),
+
+    #[error("Session error: {0}")]
+    SessionError(String),
+
+    #[error("HTTP/2 error: {0}")]
+    Http2Error(#[from] h2::Error),
+
+    #[error("TLS error: {0}")]
+    TlsError(String),
+
     #[error("Template rendering error: {0}")]
     TemplateError(String),
 